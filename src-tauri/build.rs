@@ -1,3 +1,70 @@
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
 fn main() {
+    check_extension_ids();
+    write_sidecar_manifest();
     tauri_build::build()
 }
+
+/// The published extension ids are baked into the default origin allowlist,
+/// see origins.rs. Without them the real extension is refused, so release
+/// builds on CI must have them.
+fn check_extension_ids() {
+    println!("cargo:rerun-if-env-changed=SAFEBOX_EXTENSION_IDS");
+    println!("cargo:rerun-if-env-changed=CI");
+
+    let ids = env::var("SAFEBOX_EXTENSION_IDS").unwrap_or_default();
+    if !ids.trim().is_empty() {
+        return;
+    }
+
+    let message = "SAFEBOX_EXTENSION_IDS is not set, the default origin allowlist is empty \
+                   and the SafeBox extension will be refused until it is allowed in the app";
+    let release = env::var("PROFILE").is_ok_and(|profile| profile == "release");
+    if release && env::var("CI").is_ok_and(|ci| !ci.is_empty() && ci != "false") {
+        panic!("{}", message);
+    }
+    println!("cargo:warning={}", message);
+}
+
+/// Record the SHA-256 of every bundled sidecar so the app can refuse to run
+/// a binary that was swapped after installation.
+fn write_sidecar_manifest() {
+    let target = env::var("TARGET").expect("TARGET not set");
+    // used to pick the matching build from the update manifest
+    println!("cargo:rustc-env=SAFEBOX_TARGET={}", target);
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR not set"));
+
+    let mut entries = String::new();
+    for name in ["ant", "anttp", "dweb"] {
+        // externalBin files are suffixed with the target triple, see tauri.conf.json
+        let mut file_name = format!("{}-{}", name, target);
+        if target.contains("windows") {
+            file_name.push_str(".exe");
+        }
+        let path = PathBuf::from("bin").join(file_name);
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        match fs::read(&path) {
+            Ok(bytes) => {
+                let hash = format!("{:x}", Sha256::digest(&bytes));
+                entries.push_str(&format!("    (\"{}\", \"{}\"),\n", name, hash));
+            }
+            Err(e) => println!(
+                "cargo:warning=No checksum for sidecar {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    let manifest = format!(
+        "pub const SIDECAR_HASHES: &[(&str, &str)] = &[\n{}];\n",
+        entries
+    );
+    fs::write(out_dir.join("sidecar_manifest.rs"), manifest)
+        .expect("Failed to write sidecar manifest");
+}
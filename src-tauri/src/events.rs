@@ -0,0 +1,73 @@
+use crate::types::{ServiceStateEvent, UploadFileEvent};
+use once_cell::sync::Lazy;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+// a subscriber further behind than this loses the oldest events
+const EVENT_BUFFER: usize = 256;
+
+static EVENTS: Lazy<broadcast::Sender<PushEvent>> =
+    Lazy::new(|| broadcast::channel(EVENT_BUFFER).0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+    ServiceStatus,
+    UploadProgress,
+    UploadsCompleted,
+    NetworkStatus,
+}
+
+/// Pushed to websocket clients subscribed to its topic.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(tag = "topic", content = "payload", rename_all = "snake_case")]
+pub enum PushEvent {
+    ServiceStatus(ServiceStateEvent),
+    UploadProgress(UploadProgress),
+    UploadsCompleted(UploadCompleted),
+    NetworkStatus(NetworkStatus),
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct UploadProgress {
+    pub upload_id: String,
+    /// The paired client sending the upload.
+    pub client_id: String,
+    pub received_chunks: usize,
+    pub total_chunks: usize,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct UploadCompleted {
+    pub upload_id: String,
+    pub client_id: String,
+    #[serde(flatten)]
+    pub file: UploadFileEvent,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct NetworkStatus {
+    /// The ant node, which uploads and downloads go through, is ready.
+    pub online: bool,
+}
+
+impl PushEvent {
+    pub fn topic(&self) -> Topic {
+        match self {
+            PushEvent::ServiceStatus(_) => Topic::ServiceStatus,
+            PushEvent::UploadProgress(_) => Topic::UploadProgress,
+            PushEvent::UploadsCompleted(_) => Topic::UploadsCompleted,
+            PushEvent::NetworkStatus(_) => Topic::NetworkStatus,
+        }
+    }
+}
+
+/// Hand an event to every subscriber. Nobody listening is not an error.
+pub fn publish(event: PushEvent) {
+    let _ = EVENTS.send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<PushEvent> {
+    EVENTS.subscribe()
+}
@@ -0,0 +1,210 @@
+use crate::services::Service;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// generated by build.rs from the binaries in bin/ that get bundled
+mod manifest {
+    include!(concat!(env!("OUT_DIR"), "/sidecar_manifest.rs"));
+}
+
+// hashing ant on every spawn is slow, so digests are cached until the file changes
+static HASH_CACHE: Lazy<Mutex<HashMap<PathBuf, CachedHash>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// file timestamps tick coarsely, a change this recent could still be followed
+// by another one with the same ctime
+const CACHE_SETTLE: Duration = Duration::from_secs(1);
+
+struct CachedHash {
+    stamp: FileStamp,
+    sha256: String,
+}
+
+/// What identifies a file's contents without reading them. The mtime can be
+/// set back by whoever writes the file (`touch -r`), the inode change time
+/// can't, so only the latter is used.
+#[derive(PartialEq, Eq)]
+struct FileStamp {
+    dev: u64,
+    ino: u64,
+    len: u64,
+    changed: Duration,
+}
+
+#[cfg(unix)]
+fn file_stamp(metadata: &std::fs::Metadata) -> Option<FileStamp> {
+    use std::os::unix::fs::MetadataExt;
+
+    let changed = Duration::new(
+        u64::try_from(metadata.ctime()).ok()?,
+        u32::try_from(metadata.ctime_nsec()).ok()?,
+    );
+    Some(FileStamp {
+        dev: metadata.dev(),
+        ino: metadata.ino(),
+        len: metadata.len(),
+        changed,
+    })
+}
+
+// std has no change time elsewhere, those platforms hash every time
+#[cfg(not(unix))]
+fn file_stamp(_metadata: &std::fs::Metadata) -> Option<FileStamp> {
+    None
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IntegrityReport {
+    pub service: Service,
+    pub path: String,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+pub fn bundled_hash(service: Service) -> Option<&'static str> {
+    manifest::SIDECAR_HASHES
+        .iter()
+        .find(|(name, _)| *name == service.name())
+        .map(|(_, hash)| *hash)
+}
+
+pub fn sha256_file(path: &Path) -> Result<String, String> {
+    let metadata =
+        std::fs::metadata(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    let stamp = file_stamp(&metadata);
+
+    if let Some(stamp) = &stamp {
+        if let Some(cached) = HASH_CACHE.lock().unwrap().get(path) {
+            if cached.stamp == *stamp {
+                return Ok(cached.sha256.clone());
+            }
+        }
+    }
+
+    let mut file =
+        File::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buf)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    let sha256 = format!("{:x}", hasher.finalize());
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    match stamp {
+        Some(stamp) if now.saturating_sub(stamp.changed) > CACHE_SETTLE => {
+            HASH_CACHE.lock().unwrap().insert(
+                path.to_path_buf(),
+                CachedHash {
+                    stamp,
+                    sha256: sha256.clone(),
+                },
+            );
+        }
+        _ => {
+            HASH_CACHE.lock().unwrap().remove(path);
+        }
+    }
+
+    Ok(sha256)
+}
+
+/// Compare the binary at `path` with `expected`.
+pub fn check(service: Service, path: &Path, expected: Option<&str>) -> IntegrityReport {
+    let mut report = IntegrityReport {
+        service,
+        path: path.to_string_lossy().to_string(),
+        expected: expected.map(str::to_string),
+        actual: None,
+        ok: false,
+        error: None,
+    };
+
+    match sha256_file(path) {
+        Ok(actual) => {
+            report.ok = expected.is_some_and(|expected| expected.eq_ignore_ascii_case(&actual));
+            if expected.is_none() {
+                report.error = Some(format!("No known checksum for {}", service.name()));
+            } else if !report.ok {
+                report.error = Some(format!(
+                    "{} does not match its expected checksum",
+                    path.display()
+                ));
+            }
+            report.actual = Some(actual);
+        }
+        Err(e) => report.error = Some(e),
+    }
+
+    report
+}
+
+/// Check the bundled binary of `service` against the build-time manifest.
+pub fn check_bundled(service: Service) -> IntegrityReport {
+    match service.binary_path() {
+        Ok(path) => check(service, &path, bundled_hash(service)),
+        Err(e) => IntegrityReport {
+            service,
+            path: String::new(),
+            expected: bundled_hash(service).map(str::to_string),
+            actual: None,
+            ok: false,
+            error: Some(e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn refuses_a_modified_binary_with_its_mtime_restored() {
+        let mut binary = tempfile::NamedTempFile::new().unwrap();
+        binary.write_all(b"original sidecar").unwrap();
+        binary.flush().unwrap();
+        let expected = format!("{:x}", Sha256::digest(b"original sidecar"));
+
+        // old enough for its hash to be cached
+        std::thread::sleep(CACHE_SETTLE + Duration::from_millis(100));
+        assert!(check(Service::Ant, binary.path(), Some(&expected)).ok);
+
+        let modified = binary.as_file().metadata().unwrap().modified().unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(binary.path())
+            .unwrap();
+        file.write_all(b"tampered sidecar").unwrap();
+        file.set_modified(modified).unwrap();
+        drop(file);
+
+        let metadata = std::fs::metadata(binary.path()).unwrap();
+        assert_eq!(metadata.len(), b"original sidecar".len() as u64);
+        assert_eq!(metadata.modified().unwrap(), modified);
+
+        let report = check(Service::Ant, binary.path(), Some(&expected));
+        assert!(!report.ok);
+        assert_eq!(
+            report.actual,
+            Some(format!("{:x}", Sha256::digest(b"tampered sidecar")))
+        );
+    }
+}
@@ -1,25 +1,56 @@
-use crate::types::UploadFilePayload;
+use crate::integrity::IntegrityReport;
+use crate::logging::{LogSettings, LogTarget};
+use crate::network::NetworkSettings;
+use crate::orphans::SidecarRecord;
+use crate::pairing::{PairedClient, PairingCode};
+use crate::port_owners::PortOwner;
+use crate::ports::{is_port_in_use, ManagedPort, PortAllocation, PortDiagnosis};
+use crate::quotas::UploadQuotas;
+use crate::service_logs::ServiceLogEntry;
+use crate::services::{Service, ShutdownOutcome};
+use crate::sidecar_config::SidecarConfig;
+use crate::types::{ServiceStatus, ShutdownReport};
+use crate::updates::{AvailableUpdate, InstalledVersion, UpdateSettings};
 use crate::websockets::start_websocket_server;
 use crate::websockets::stop_websocket_server;
 use crate::websockets::WEBSOCKET_SHUTDOWN_TX;
 use crate::websockets::WEBSOCKET_TASK_HANDLE;
 use once_cell::sync::Lazy;
 use std::env;
-use std::io::Write;
-use std::net::TcpListener;
 use std::path::PathBuf;
 use std::process::Command;
 use std::sync::Mutex;
+use tauri::ipc::Channel;
 use tauri::Emitter;
 use tauri::WindowEvent;
 use tauri::{AppHandle, Window};
 use tauri_plugin_dialog::DialogExt;
-use tauri_plugin_shell::process::{CommandChild, CommandEvent};
-use tauri_plugin_shell::ShellExt;
+use tauri_plugin_shell::process::CommandEvent;
 use tauri_plugin_store::StoreBuilder;
-use tempfile::NamedTempFile;
 
+// declared first so its log macros are visible in the modules below
+#[macro_use]
+mod logging;
+
+mod events;
+mod integrity;
+mod network;
+mod origins;
+mod orphans;
+mod pairing;
+mod port_owners;
+mod ports;
+mod protocol;
+mod quotas;
+mod rest_api;
+mod service_logs;
+mod services;
+mod settings;
+mod sidecar_config;
 mod types;
+mod updates;
+mod upload_frames;
+mod uploads;
 mod websockets;
 
 pub static ANT_PORT: Lazy<Mutex<u16>> = Lazy::new(|| Mutex::new(8081));
@@ -27,14 +58,6 @@ pub static ANTTP_PORT: Lazy<Mutex<u16>> = Lazy::new(|| Mutex::new(18888));
 pub static DWEB_PORT: Lazy<Mutex<u16>> = Lazy::new(|| Mutex::new(5537));
 pub static WEBSOCKET_PORT: Lazy<Mutex<u16>> = Lazy::new(|| Mutex::new(8084));
 
-static ANT_PROCESS: Lazy<Mutex<Option<CommandChild>>> = Lazy::new(|| Mutex::new(None));
-static ANTPP_PROCESS: Lazy<Mutex<Option<CommandChild>>> = Lazy::new(|| Mutex::new(None));
-static DWEB_PROCESS: Lazy<Mutex<Option<CommandChild>>> = Lazy::new(|| Mutex::new(None));
-
-fn is_port_in_use(port: u16) -> bool {
-    TcpListener::bind(("127.0.0.1", port)).is_err()
-}
-
 #[tauri::command]
 fn get_ports() -> Result<(u16, u16, u16, u16), String> {
     let ant_port = *ANT_PORT.lock().unwrap();
@@ -44,44 +67,136 @@ fn get_ports() -> Result<(u16, u16, u16, u16), String> {
     Ok((ant_port, anttp_port, dweb_port, websocket_port))
 }
 
-fn set_port(mutex: &Mutex<u16>, port: u16) -> Result<(), String> {
-    if port == 0 {
-        return Err("Port must be > 0".into());
+/// Move a sidecar to another port. A running sidecar is restarted there and
+/// goes back to its old port if it doesn't come up on the new one.
+async fn change_service_port(app: &AppHandle, service: Service, port: u16) -> Result<(), String> {
+    ports::validate(service.into(), port)?;
+
+    let previous_port = service.port();
+    if previous_port == port || !services::is_running(service) {
+        service.set_port(port);
+        return ports::save(app, service.into(), port);
     }
-    *mutex.lock().unwrap() = port;
-    Ok(())
+
+    let report = services::stop(service).await;
+    services::emit_state(app, service, Some(format!("Moving to port {}", port)));
+    if let ShutdownOutcome::Failed(e) = report.outcome {
+        return Err(format!("Failed to stop {}: {}", service.name(), e));
+    }
+
+    service.set_port(port);
+    if let Err(e) = start_and_wait_ready(app, service).await {
+        log_warn!(
+            LogTarget::Backend,
+            "{} failed on port {}, going back to {}: {}",
+            service.name(),
+            port,
+            previous_port,
+            e
+        );
+
+        services::stop(service).await;
+        service.set_port(previous_port);
+        if let Err(restore_error) = start_and_wait_ready(app, service).await {
+            log_error!(
+                LogTarget::Backend,
+                "Failed to restore {} on port {}: {}",
+                service.name(),
+                previous_port,
+                restore_error
+            );
+        }
+        services::emit_state(app, service, None);
+
+        return Err(format!(
+            "{} did not come up on port {}: {}",
+            service.name(),
+            port,
+            e
+        ));
+    }
+
+    ports::save(app, service.into(), port)
+}
+
+async fn start_and_wait_ready(app: &AppHandle, service: Service) -> Result<(), String> {
+    wait_for_port_release(service.port()).await;
+    ensure_port_free(app, service.into())?;
+    services::start(app, service)?;
+    services::wait_ready(service).await
+}
+
+/// Give a process that was just stopped a moment to release its port.
+async fn wait_for_port_release(port: u16) {
+    for _ in 0..20 {
+        if !is_port_in_use(port) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+}
+
+#[tauri::command]
+fn get_port_allocation() -> PortAllocation {
+    ports::get_allocation()
+}
+
+#[tauri::command]
+fn set_port_allocation(app: AppHandle, allocation: PortAllocation) -> Result<(), String> {
+    ports::set_allocation(&app, allocation)
+}
+
+#[tauri::command]
+fn get_upload_quotas() -> UploadQuotas {
+    quotas::get()
+}
+
+#[tauri::command]
+fn set_upload_quotas(app: AppHandle, quotas: UploadQuotas) -> Result<(), String> {
+    quotas::set(&app, quotas)
 }
 
 #[tauri::command]
-fn set_ant_port(port: u16) -> Result<(), String> {
-    set_port(&ANT_PORT, port)
+async fn set_ant_port(app: AppHandle, port: u16) -> Result<(), String> {
+    change_service_port(&app, Service::Ant, port).await
 }
 
 #[tauri::command]
-fn set_anttp_port(port: u16) -> Result<(), String> {
-    set_port(&ANTTP_PORT, port)
+async fn set_anttp_port(app: AppHandle, port: u16) -> Result<(), String> {
+    change_service_port(&app, Service::Anttp, port).await
 }
 
 #[tauri::command]
-fn set_dweb_port(port: u16) -> Result<(), String> {
-    set_port(&DWEB_PORT, port)
+async fn set_dweb_port(app: AppHandle, port: u16) -> Result<(), String> {
+    change_service_port(&app, Service::Dweb, port).await
 }
 
 #[tauri::command]
 async fn set_websocket_port(port: u16, app_handle: tauri::AppHandle) -> Result<(), String> {
-    if port == 0 {
-        return Err("Port must be > 0".into());
-    }
+    ports::validate(ManagedPort::Websocket, port)?;
 
-    {
+    let previous_port = {
         let mut port_lock = WEBSOCKET_PORT.lock().unwrap();
 
-        *port_lock = port;
-    }
+        std::mem::replace(&mut *port_lock, port)
+    };
 
     // stop current WS server
     stop_websocket_server().await?;
 
+    // keep the old port if the new one can't be had
+    if let Err(e) = ensure_port_free(&app_handle, ManagedPort::Websocket) {
+        *WEBSOCKET_PORT.lock().unwrap() = previous_port;
+        spawn_websocket_server(&app_handle).await;
+        return Err(e);
+    }
+
+    spawn_websocket_server(&app_handle).await;
+
+    ports::save(&app_handle, ManagedPort::Websocket, port)
+}
+
+async fn spawn_websocket_server(app_handle: &AppHandle) {
     // create a new watch channel for shutdown signaling
     let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
 
@@ -100,8 +215,6 @@ async fn set_websocket_port(port: u16, app_handle: tauri::AppHandle) -> Result<(
         let mut task_handle_lock = WEBSOCKET_TASK_HANDLE.lock().await;
         *task_handle_lock = Some(join_handle);
     }
-
-    Ok(())
 }
 
 fn handle_permission_error(window: &Window, binary_name: &str) {
@@ -142,50 +255,61 @@ fn handle_permission_error(window: &Window, binary_name: &str) {
 }
 
 #[tauri::command]
-fn kill_process_on_port(port: u16) -> Result<(), String> {
-    use std::process::Command;
-
-    #[cfg(target_os = "windows")]
-    let output = Command::new("cmd")
-        .args(["/C", &format!("netstat -ano | findstr :{}", port)])
-        .output()
-        .map_err(|e| e.to_string())?;
-
-    #[cfg(not(target_os = "windows"))]
-    let output = Command::new("sh")
-        .args(["-c", &format!("lsof -t -i :{}", port)])
-        .output()
-        .map_err(|e| e.to_string())?;
+async fn diagnose_ports() -> Result<Vec<PortDiagnosis>, String> {
+    tauri::async_runtime::spawn_blocking(ports::diagnose)
+        .await
+        .map_err(|e| format!("Port diagnosis failed: {}", e))
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
+#[tauri::command]
+fn find_port_owners(port: u16) -> Result<Vec<PortOwner>, String> {
+    port_owners::find(port)
+}
 
-    #[cfg(target_os = "windows")]
+/// Kill a process the user picked from `find_port_owners`. It must still be
+/// listening on `port`, so a stale confirmation can't hit a reused pid.
+#[tauri::command]
+async fn kill_process_on_port(port: u16, pid: u32) -> Result<ShutdownOutcome, String> {
+    let owner = port_owners::find(port)?
+        .into_iter()
+        .find(|owner| owner.pid == Some(pid))
+        .ok_or_else(|| format!("pid {} is not listening on port {}", pid, port))?;
+
+    if !owner.killable {
+        return Err(format!(
+            "Refusing to kill pid {}, it belongs to {}",
+            pid,
+            owner.user.as_deref().unwrap_or("another user")
+        ));
+    }
+    if let Some(service) = Service::ALL
+        .into_iter()
+        .find(|service| services::pid(*service) == Some(pid))
     {
-        for line in stdout.lines() {
-            if let Some(pid) = line.split_whitespace().last() {
-                Command::new("taskkill")
-                    .args(["/PID", pid, "/F"])
-                    .output()
-                    .map_err(|e| e.to_string())?;
-            }
-        }
+        return Err(format!(
+            "pid {} is the running {} sidecar, stop it instead",
+            pid,
+            service.name()
+        ));
     }
 
-    #[cfg(not(target_os = "windows"))]
-    {
-        for pid in stdout.lines() {
-            Command::new("kill")
-                .arg("-9")
-                .arg(pid)
-                .output()
-                .map_err(|e| e.to_string())?;
-        }
+    log_info!(
+        LogTarget::Backend,
+        "Killing {} (pid {}) holding port {}",
+        owner.name.as_deref().unwrap_or("unknown process"),
+        pid,
+        port
+    );
+
+    let outcome = port_owners::kill(pid).await;
+    if let ShutdownOutcome::Failed(e) = &outcome {
+        return Err(format!("Failed to kill pid {}: {}", pid, e));
     }
 
-    Ok(())
+    Ok(outcome)
 }
 
-fn get_app_store_path() -> Result<PathBuf, String> {
+fn get_app_data_dir() -> Result<PathBuf, String> {
     // get the OS-specific user data directory (e.g. on Windows: C:\Users\<User>\AppData\Roaming)
     let mut app_dir = dirs::data_dir().ok_or("Could not find data directory")?;
 
@@ -195,6 +319,12 @@ fn get_app_store_path() -> Result<PathBuf, String> {
     // create the directory if it doesn't exist yet (optional)
     std::fs::create_dir_all(&app_dir).map_err(|e| format!("Failed to create app dir: {}", e))?;
 
+    Ok(app_dir)
+}
+
+fn get_app_store_path() -> Result<PathBuf, String> {
+    let mut app_dir = get_app_data_dir()?;
+
     // append your store file name
     app_dir.push("store.bin");
 
@@ -203,7 +333,7 @@ fn get_app_store_path() -> Result<PathBuf, String> {
 
 #[tauri::command]
 async fn import_wallet(app_handle: tauri::AppHandle) -> Result<(), String> {
-    println!("Import wallet started");
+    log_info!(LogTarget::Backend, "Import wallet started");
 
     // use your get_app_store_path function to get the store path
     let store_path = get_app_store_path()?;
@@ -212,7 +342,10 @@ async fn import_wallet(app_handle: tauri::AppHandle) -> Result<(), String> {
     let wallet_store = match StoreBuilder::new(&app_handle, store_path.clone()).build() {
         Ok(store) => store,
         Err(e) => {
-            println!("Warning: Failed to build store (continuing without wallet): {e}");
+            log_warn!(
+                LogTarget::Backend,
+                "Warning: Failed to build store (continuing without wallet): {e}"
+            );
             return Ok(()); // continue without wallet
         }
     };
@@ -225,10 +358,7 @@ async fn import_wallet(app_handle: tauri::AppHandle) -> Result<(), String> {
     if let Some(serde_json::Value::Object(key_map)) = key_entry {
         if let Some(serde_json::Value::String(private_key)) = key_map.get("value") {
             if !private_key.trim().is_empty() {
-                let import_cmd = app_handle
-                    .shell()
-                    .sidecar("ant")
-                    .map_err(|e| format!("Failed to create ant sidecar: {}", e))?;
+                let import_cmd = services::sidecar_command(&app_handle, Service::Ant)?;
 
                 // build args as owned Strings
                 let mut args: Vec<String> = vec![
@@ -259,47 +389,47 @@ async fn import_wallet(app_handle: tauri::AppHandle) -> Result<(), String> {
                     Ok(output) => {
                         if !output.status.success() {
                             let stderr = String::from_utf8_lossy(&output.stderr);
-                            println!(
+                            log_warn!(
+                                LogTarget::Backend,
                                 "Warning: Wallet import failed (continuing without wallet): {stderr}"
                             );
                         } else {
-                            println!("Wallet imported successfully.");
+                            log_info!(LogTarget::Backend, "Wallet imported successfully.");
                         }
                     }
                     Err(e) => {
-                        println!("Warning: Failed to import wallet: {e}");
+                        log_warn!(LogTarget::Backend, "Warning: Failed to import wallet: {e}");
                     }
                 }
             } else {
-                println!("Warning: Private key is empty (skipping wallet import)");
+                log_warn!(
+                    LogTarget::Backend,
+                    "Warning: Private key is empty (skipping wallet import)"
+                );
             }
         } else {
-            println!("Warning: 'value' field is missing or not a string in wallet-private-key");
+            log_warn!(
+                LogTarget::Backend,
+                "Warning: 'value' field is missing or not a string in wallet-private-key"
+            );
         }
     } else {
-        println!("No private key in store (wallet not imported)");
+        log_info!(
+            LogTarget::Backend,
+            "No private key in store (wallet not imported)"
+        );
     }
 
     Ok(())
 }
 
-pub async fn do_upload(payload: UploadFilePayload, handle: &AppHandle) -> Result<String, String> {
-    // create a temporary file synchronously
-    let mut temp_file =
-        NamedTempFile::new().map_err(|e| format!("Failed to create temp file: {}", e))?;
-
-    // write data to temp file synchronously because NamedTempFile uses std::fs::File
-    temp_file
-        .write_all(&payload.data)
-        .map_err(|e| format!("Failed to write to temp file: {}", e))?;
-
-    let file_path = temp_file.path();
-
+/// Upload a file already on disk, returning its xorname.
+pub async fn upload_path(
+    file_path: &std::path::Path,
+    handle: &AppHandle,
+) -> Result<String, String> {
     // launch ant sidecar command
-    let ant_cmd = handle
-        .shell()
-        .sidecar("ant")
-        .map_err(|e| format!("Failed to create ant sidecar: {}", e))?;
+    let ant_cmd = services::sidecar_command(handle, Service::Ant)?;
 
     let output = ant_cmd
         .args([
@@ -320,182 +450,414 @@ pub async fn do_upload(payload: UploadFilePayload, handle: &AppHandle) -> Result
     }
 }
 
+/// 32 bytes in hex, the address of a file on the network.
+pub fn is_xorname(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Fetch a file from the network into `dest`.
+pub async fn download_path(
+    xorname: &str,
+    dest: &std::path::Path,
+    handle: &AppHandle,
+) -> Result<(), String> {
+    // also keeps anything that looks like a flag away from the ant command line
+    if !is_xorname(xorname) {
+        return Err(format!("'{}' is not an xorname", xorname));
+    }
+
+    let ant_cmd = services::sidecar_command(handle, Service::Ant)?;
+
+    let output = ant_cmd
+        .args([
+            "file",
+            "download",
+            xorname,
+            dest.to_str().ok_or("Invalid file path")?,
+        ])
+        .output()
+        .await
+        .map_err(|e| format!("Failed to execute ant: {}", e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        let err = String::from_utf8_lossy(&output.stderr);
+        Err(format!("ant download failed: {}", err))
+    }
+}
+
 #[tauri::command]
-fn is_server_running() -> bool {
-    let ant_lock = ANT_PROCESS.lock().unwrap();
-    let anttp_lock = ANTPP_PROCESS.lock().unwrap();
-    let dweb_lock = DWEB_PROCESS.lock().unwrap();
+async fn get_service_status() -> Vec<ServiceStatus> {
+    services::status().await
+}
+
+/// Moves `port` within the configured range if it's taken and auto allocation
+/// is on, and tells the frontend where it went.
+fn ensure_port_free(app: &AppHandle, port: ManagedPort) -> Result<(), String> {
+    if let Some(reassignment) = ports::ensure_free(port)? {
+        let _ = app.emit("port-reassigned", reassignment);
+    }
+    Ok(())
+}
 
-    ant_lock.is_some() || anttp_lock.is_some() || dweb_lock.is_some()
+fn check_port_free(app: &AppHandle, window: &Window, service: Service) -> Result<(), String> {
+    if let Err(e) = ensure_port_free(app, service.into()) {
+        let _ = window
+            .dialog()
+            .message(format!("{}. Cannot start '{}'.", e, service.name()));
+        return Err(e);
+    }
+    Ok(())
 }
 
 #[tauri::command]
 async fn start_server(app: AppHandle, window: Window) -> Result<String, String> {
-    let mut ant_lock = ANT_PROCESS.lock().unwrap();
-    let mut anttp_lock = ANTPP_PROCESS.lock().unwrap();
-    let mut dweb_lock = DWEB_PROCESS.lock().unwrap();
+    // only start what isn't already up, so a single running service doesn't block the rest
+    let pending: Vec<Service> = Service::ALL
+        .into_iter()
+        .filter(|service| !services::is_running(*service))
+        .collect();
 
-    let ant_port = *ANT_PORT.lock().unwrap();
-    let anttp_port = *ANTTP_PORT.lock().unwrap();
-    let dweb_port = *DWEB_PORT.lock().unwrap();
-
-    if ant_lock.is_some() || anttp_lock.is_some() || dweb_lock.is_some() {
+    if pending.is_empty() {
         return Err("Services already running".into());
     }
 
     // check ports before starting
-    if is_port_in_use(ant_port) {
-        let _ = window.dialog().message(format!(
-            "Port {} is already in use. Cannot start 'ant'.",
-            ant_port
-        ));
-        return Err(format!("Port {} in use", ant_port));
+    for service in &pending {
+        check_port_free(&app, &window, *service)?;
     }
 
-    if is_port_in_use(anttp_port) {
-        let _ = window.dialog().message(format!(
-            "Port {} is already in use. Cannot start 'anttp'.",
-            anttp_port
-        ));
-        return Err(format!("Port {} in use", anttp_port));
-    }
+    services::start_batch(&app, &pending).await?;
 
-    if is_port_in_use(dweb_port) {
-        let _ = window.dialog().message(format!(
-            "Port {} is already in use. Cannot start 'dweb'.",
-            dweb_port
-        ));
-        return Err(format!("Port {} in use", dweb_port));
+    Ok(format!(
+        "ant, anttp and dweb started on ports {}, {} and {}",
+        Service::Ant.port(),
+        Service::Anttp.port(),
+        Service::Dweb.port()
+    ))
+}
+
+#[tauri::command]
+async fn start_service(app: AppHandle, window: Window, name: Service) -> Result<String, String> {
+    if services::is_running(name) {
+        return Err(format!("{} is already running", name.name()));
     }
 
-    // --- Start ant ---
-    let ant_cmd = app
-        .shell()
-        .sidecar("ant")
-        .map_err(|e| format!("Failed to create ant sidecar: {}", e))?;
+    check_port_free(&app, &window, name)?;
+    services::start(&app, name)?;
 
-    let (mut ant_rx, ant_child) = ant_cmd
-        .args(["-l", &format!("127.0.0.1:{}", ant_port)])
-        .spawn()
-        .map_err(|e| format!("Failed to spawn ant: {}", e))?;
+    Ok(format!("{} started", name.name()))
+}
 
-    *ant_lock = Some(ant_child);
+#[tauri::command]
+async fn stop_service(app: AppHandle, name: Service) -> Result<ShutdownReport, String> {
+    let report = services::stop(name).await;
+    services::emit_state(&app, name, None);
 
-    let window_clone_for_ant = window.clone();
-    tauri::async_runtime::spawn(async move {
-        while let Some(event) = ant_rx.recv().await {
-            if let CommandEvent::Stdout(bytes) = event {
-                let line = String::from_utf8_lossy(&bytes);
-                let _ = window_clone_for_ant.emit("ant-message", Some(line.to_string()));
-            }
-        }
-    });
+    if let ShutdownOutcome::Failed(e) = &report.outcome {
+        return Err(format!("Failed to stop {}: {}", name.name(), e));
+    }
 
-    // --- Start anttp ---
-    let anttp_cmd = app
-        .shell()
-        .sidecar("anttp")
-        .map_err(|e| format!("Failed to create anttp sidecar: {}", e))?;
+    Ok(report)
+}
 
-    let (mut anttp_rx, anttp_child) = anttp_cmd
-        .args(["-l", &format!("127.0.0.1:{}", anttp_port)])
-        .spawn()
-        .map_err(|e| format!("Failed to spawn anttp: {}", e))?;
+#[tauri::command]
+async fn restart_service(app: AppHandle, window: Window, name: Service) -> Result<String, String> {
+    let report = services::stop(name).await;
+    services::emit_state(&app, name, None);
 
-    *anttp_lock = Some(anttp_child);
+    if let ShutdownOutcome::Failed(e) = report.outcome {
+        return Err(format!("Failed to stop {}: {}", name.name(), e));
+    }
 
-    let window_clone_for_anttp = window.clone();
-    tauri::async_runtime::spawn(async move {
-        while let Some(event) = anttp_rx.recv().await {
-            if let CommandEvent::Stdout(bytes) = event {
-                let line = String::from_utf8_lossy(&bytes);
-                let _ = window_clone_for_anttp.emit("anttp-message", Some(line.to_string()));
-            }
-        }
-    });
+    wait_for_port_release(name.port()).await;
 
-    // --- Start dweb ---
-    let dweb_cmd = app
-        .shell()
-        .sidecar("dweb")
-        .map_err(|e| format!("Failed to create dweb sidecar: {}", e))?;
+    check_port_free(&app, &window, name)?;
+    services::start(&app, name)?;
 
-    let (mut dweb_rx, dweb_child) = dweb_cmd
-        .args(["serve", "--port", &dweb_port.to_string()])
-        .spawn()
-        .map_err(|e| format!("Failed to spawn dweb: {}", e))?;
+    Ok(format!("{} restarted", name.name()))
+}
 
-    *dweb_lock = Some(dweb_child);
+#[tauri::command]
+fn get_service_logs(
+    service: Service,
+    since: Option<u64>,
+    limit: Option<usize>,
+) -> Vec<ServiceLogEntry> {
+    service_logs::query(service, since, limit)
+}
 
-    let window_clone_for_dweb = window.clone();
-    tauri::async_runtime::spawn(async move {
-        while let Some(event) = dweb_rx.recv().await {
-            if let CommandEvent::Stdout(bytes) = event {
-                let line = String::from_utf8_lossy(&bytes);
-                let _ = window_clone_for_dweb.emit("dweb-message", Some(line.to_string()));
-            }
-        }
-    });
+#[tauri::command]
+fn subscribe_service_logs(service: Option<Service>, on_entry: Channel<ServiceLogEntry>) -> u64 {
+    service_logs::subscribe(service, on_entry)
+}
 
-    Ok("ant, anttp and dweb started".into())
+#[tauri::command]
+fn unsubscribe_service_logs(id: u64) -> bool {
+    service_logs::unsubscribe(id)
 }
 
-macro_rules! kill_child {
-    ($lock:expr) => {
-        if let Some(child) = $lock.lock().unwrap().take() {
-            let _ = child.kill();
-        }
-    };
+#[tauri::command]
+fn get_sidecar_config(service: Service) -> SidecarConfig {
+    sidecar_config::get(service)
 }
 
 #[tauri::command]
-fn stop_server() -> Result<String, String> {
-    let mut ant_lock = ANT_PROCESS.lock().unwrap();
-    let mut anttp_lock = ANTPP_PROCESS.lock().unwrap();
-    let mut dweb_lock = DWEB_PROCESS.lock().unwrap();
+fn set_sidecar_config(
+    app: AppHandle,
+    service: Service,
+    config: SidecarConfig,
+) -> Result<(), String> {
+    sidecar_config::set(&app, service, config)
+}
 
-    // helper to stop each sidecar safely
-    fn stop_process(name: &str, proc_opt: &mut Option<CommandChild>) -> Result<(), String> {
-        if let Some(mut child) = proc_opt.take() {
-            if let Err(e) = child.kill() {
-                return Err(format!("Failed to kill {}: {}", name, e));
-            }
+#[tauri::command]
+fn get_log_dir() -> Result<String, String> {
+    Ok(logging::log_dir()?.to_string_lossy().to_string())
+}
+
+#[tauri::command]
+fn get_network_settings() -> NetworkSettings {
+    network::get_settings()
+}
+
+/// Addresses beyond loopback need `confirmed`. The websocket server and any
+/// running sidecars are restarted on the new address.
+#[tauri::command]
+async fn set_network_settings(
+    app: AppHandle,
+    settings: NetworkSettings,
+    confirmed: bool,
+) -> Result<(), String> {
+    let previous = network::get_settings();
+    let bind_changed = previous.bind != settings.bind;
+    let socket_changed = previous.unix_socket != settings.unix_socket;
+    network::set_settings(&app, settings, confirmed)?;
+
+    // allowlist changes apply to the next connection on their own
+    if bind_changed || socket_changed {
+        stop_websocket_server().await?;
+        spawn_websocket_server(&app).await;
+    }
+    if !bind_changed {
+        return Ok(());
+    }
+
+    let mut failures = Vec::new();
+    for service in Service::ALL {
+        if !services::is_running(service) {
+            continue;
+        }
+        services::stop(service).await;
+        if let Err(e) = start_and_wait_ready(&app, service).await {
+            failures.push(format!("{}: {}", service.name(), e));
         }
-        Ok(())
     }
 
-    stop_process("ant", &mut ant_lock)?;
-    stop_process("anttp", &mut anttp_lock)?;
-    stop_process("dweb", &mut dweb_lock)?;
+    if !failures.is_empty() {
+        return Err(format!("Failed to restart {}", failures.join(", ")));
+    }
 
-    Ok("ant, anttp and dweb stopped".into())
+    Ok(())
 }
 
-fn cleanup_processes() {
-    let mut ant_lock = ANT_PROCESS.lock().unwrap();
-    let mut anttp_lock = ANTPP_PROCESS.lock().unwrap();
-    let mut dweb_lock = DWEB_PROCESS.lock().unwrap();
+#[tauri::command]
+fn get_unix_socket_path() -> Option<String> {
+    if !network::get_settings().unix_socket {
+        return None;
+    }
+    network::unix_socket_path()
+        .ok()
+        .map(|path| path.to_string_lossy().to_string())
+}
+
+#[tauri::command]
+fn get_allowed_origins() -> Vec<String> {
+    origins::get()
+}
+
+#[tauri::command]
+fn set_allowed_origins(app: AppHandle, origins: Vec<String>) -> Result<(), String> {
+    origins::set(&app, origins)
+}
+
+#[tauri::command]
+fn start_pairing() -> PairingCode {
+    pairing::start()
+}
+
+#[tauri::command]
+fn list_paired_clients() -> Vec<PairedClient> {
+    pairing::list()
+}
+
+#[tauri::command]
+fn revoke_paired_client(app: AppHandle, id: String) -> Result<(), String> {
+    pairing::revoke(&app, &id)
+}
 
-    tauri::async_runtime::block_on(stop_websocket_server()).ok();
+#[tauri::command]
+fn get_log_settings() -> LogSettings {
+    logging::get_settings()
+}
+
+#[tauri::command]
+fn set_log_settings(app: AppHandle, settings: LogSettings) -> Result<(), String> {
+    logging::set_settings(&app, settings)
+}
+
+#[tauri::command]
+async fn stop_server(app: AppHandle) -> Result<Vec<ShutdownReport>, String> {
+    let reports = services::stop_all().await;
+
+    for report in &reports {
+        services::emit_state(&app, report.service, None);
+    }
+
+    let failures: Vec<String> = reports
+        .iter()
+        .filter_map(|report| match &report.outcome {
+            ShutdownOutcome::Failed(e) => Some(format!("{}: {}", report.service.name(), e)),
+            _ => None,
+        })
+        .collect();
 
-    if let Some(ant_child) = ant_lock.take() {
-        let _ = ant_child.kill();
+    if !failures.is_empty() {
+        return Err(format!("Failed to stop {}", failures.join(", ")));
     }
-    if let Some(anttp_child) = anttp_lock.take() {
-        let _ = anttp_child.kill();
+
+    Ok(reports)
+}
+
+#[tauri::command]
+async fn verify_sidecars() -> Result<Vec<IntegrityReport>, String> {
+    tauri::async_runtime::spawn_blocking(|| {
+        Service::ALL
+            .into_iter()
+            .map(|service| match updates::active_binary(service) {
+                Ok(active) => integrity::check(service, &active.path, active.sha256.as_deref()),
+                Err(_) => integrity::check_bundled(service),
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| format!("Integrity check failed: {}", e))
+}
+
+#[tauri::command]
+fn get_sidecar_updates() -> UpdateSettings {
+    updates::get_settings()
+}
+
+#[tauri::command]
+fn set_update_manifest_url(app: AppHandle, url: Option<String>) -> Result<(), String> {
+    updates::set_manifest_url(&app, url)
+}
+
+#[tauri::command]
+async fn check_sidecar_updates() -> Result<Vec<AvailableUpdate>, String> {
+    updates::check().await
+}
+
+#[tauri::command]
+async fn install_sidecar_version(
+    app: AppHandle,
+    service: Service,
+    version: String,
+) -> Result<InstalledVersion, String> {
+    updates::install(&app, service, &version).await
+}
+
+/// Takes effect on the next start of the sidecar.
+#[tauri::command]
+fn select_sidecar_version(
+    app: AppHandle,
+    service: Service,
+    version: Option<String>,
+    pin: bool,
+) -> Result<(), String> {
+    updates::select(&app, service, version, pin)
+}
+
+#[tauri::command]
+fn rollback_sidecar_version(app: AppHandle, service: Service) -> Result<Option<String>, String> {
+    updates::rollback(&app, service)
+}
+
+#[tauri::command]
+async fn update_sidecar(app: AppHandle, service: Service) -> Result<Option<String>, String> {
+    updates::update(&app, service).await
+}
+
+#[tauri::command]
+fn list_orphaned_sidecars() -> Vec<SidecarRecord> {
+    orphans::list()
+}
+
+#[tauri::command]
+fn adopt_orphaned_sidecar(app: AppHandle, service: Service) -> Result<(), String> {
+    let record = orphans::verified(service)?;
+
+    // the orphan keeps listening where it was started, advertise that port
+    let previous_port = service.port();
+    service.set_port(record.port);
+
+    if let Err(e) = services::adopt(&app, service, record.pid) {
+        service.set_port(previous_port);
+        return Err(e);
     }
-    if let Some(dweb_child) = dweb_lock.take() {
-        let _ = dweb_child.kill();
+    orphans::resolve(service);
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn terminate_orphaned_sidecar(service: Service) -> Result<ShutdownOutcome, String> {
+    let record = orphans::verified(service)?;
+
+    let outcome = services::terminate_pid(record.pid, services::shutdown_grace_period()).await;
+    if let ShutdownOutcome::Failed(e) = &outcome {
+        return Err(format!("Failed to terminate {}: {}", service.name(), e));
     }
+
+    orphans::resolve(service);
+    orphans::forget(service);
+
+    Ok(outcome)
+}
+
+#[tauri::command]
+fn get_shutdown_grace_period() -> u64 {
+    services::shutdown_grace_period().as_millis() as u64
+}
+
+#[tauri::command]
+fn set_shutdown_grace_period(app: AppHandle, grace_ms: u64) -> Result<(), String> {
+    services::set_shutdown_grace_period(&app, grace_ms)
+}
+
+fn cleanup_processes() {
+    tauri::async_runtime::block_on(async {
+        stop_websocket_server().await.ok();
+
+        for report in services::stop_all().await {
+            log_info!(
+                LogTarget::Backend,
+                "{} shutdown: {:?} (exit code {:?})",
+                report.service.name(),
+                report.outcome,
+                report.exit_code
+            );
+        }
+    });
 }
 
 #[tauri::command]
-async fn get_binary_version(app: AppHandle, binary_name: String) -> Result<String, String> {
+async fn get_binary_version(app: AppHandle, binary_name: Service) -> Result<String, String> {
     // Use Tauri sidecar API: it will resolve to the correct path
-    let cmd = app
-        .shell()
-        .sidecar(&binary_name)
-        .map_err(|e| format!("Failed to create sidecar for {}: {}", binary_name, e))?;
+    let cmd = services::sidecar_command(&app, binary_name)?;
+    let binary_name = binary_name.name();
 
     let (mut rx, _child) = cmd
         .args(["--version"])
@@ -548,13 +910,51 @@ pub fn run() {
         .invoke_handler(tauri::generate_handler![
             start_server,
             stop_server,
+            start_service,
+            stop_service,
+            restart_service,
             get_ports,
             set_ant_port,
             set_anttp_port,
             set_dweb_port,
             set_websocket_port,
+            get_port_allocation,
+            set_port_allocation,
+            get_upload_quotas,
+            set_upload_quotas,
+            diagnose_ports,
+            find_port_owners,
             kill_process_on_port,
-            is_server_running,
+            get_service_status,
+            get_service_logs,
+            subscribe_service_logs,
+            unsubscribe_service_logs,
+            get_sidecar_config,
+            set_sidecar_config,
+            verify_sidecars,
+            get_sidecar_updates,
+            set_update_manifest_url,
+            check_sidecar_updates,
+            install_sidecar_version,
+            select_sidecar_version,
+            rollback_sidecar_version,
+            update_sidecar,
+            list_orphaned_sidecars,
+            adopt_orphaned_sidecar,
+            terminate_orphaned_sidecar,
+            get_shutdown_grace_period,
+            set_shutdown_grace_period,
+            get_log_dir,
+            get_network_settings,
+            set_network_settings,
+            get_unix_socket_path,
+            get_allowed_origins,
+            set_allowed_origins,
+            start_pairing,
+            list_paired_clients,
+            revoke_paired_client,
+            get_log_settings,
+            set_log_settings,
             get_binary_version,
             import_wallet,
         ])
@@ -562,6 +962,27 @@ pub fn run() {
             let handle = app.handle();
             let handle_clone = handle.clone();
 
+            logging::init(handle);
+            sidecar_config::init(handle);
+            network::init(handle);
+            pairing::init(handle);
+            origins::init(handle);
+            services::init(handle);
+            updates::init(handle);
+            ports::init(handle);
+            uploads::init(handle);
+            quotas::init(handle);
+
+            let orphaned = orphans::detect();
+            if !orphaned.is_empty() {
+                let _ = handle.emit("orphaned-sidecars", orphaned);
+            }
+            log_info!(
+                LogTarget::Backend,
+                "SafeBox Client {} starting",
+                handle.package_info().version
+            );
+
             // register ctrl-c handler once at startup
             ctrlc::set_handler(|| {
                 cleanup_processes();
@@ -569,6 +990,10 @@ pub fn run() {
             })
             .expect("Error setting Ctrl-C handler");
 
+            if let Err(e) = ensure_port_free(handle, ManagedPort::Websocket) {
+                log_warn!(LogTarget::Websocket, "{}", e);
+            }
+
             let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
 
             // store the shutdown tx so you can stop it later
@@ -590,12 +1015,6 @@ pub fn run() {
 
             Ok(())
         })
-        .on_window_event(|_window, event| {
-            if let WindowEvent::CloseRequested { api: _, .. } = event {
-                // cleanup on window close
-                cleanup_processes();
-            }
-        })
         .on_window_event(|_window, event| {
             if let WindowEvent::CloseRequested { .. } = event {
                 cleanup_processes(); // cleanup on window close
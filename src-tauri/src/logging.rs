@@ -0,0 +1,217 @@
+use crate::services::Service;
+use crate::settings;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::AppHandle;
+
+const LOG_SETTINGS_KEY: &str = "log-settings";
+
+static LOG_SETTINGS: Lazy<Mutex<LogSettings>> = Lazy::new(|| Mutex::new(LogSettings::default()));
+static LOG_FILES: Lazy<Mutex<HashMap<LogTarget, RotatingFile>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+macro_rules! log_info {
+    ($target:expr, $($arg:tt)*) => {
+        $crate::logging::write($target, "INFO", &format!($($arg)*))
+    };
+}
+
+macro_rules! log_warn {
+    ($target:expr, $($arg:tt)*) => {
+        $crate::logging::write($target, "WARN", &format!($($arg)*))
+    };
+}
+
+macro_rules! log_error {
+    ($target:expr, $($arg:tt)*) => {
+        $crate::logging::write($target, "ERROR", &format!($($arg)*))
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LogTarget {
+    Ant,
+    Anttp,
+    Dweb,
+    Websocket,
+    Backend,
+}
+
+impl LogTarget {
+    fn file_name(self) -> &'static str {
+        match self {
+            LogTarget::Ant => "ant.log",
+            LogTarget::Anttp => "anttp.log",
+            LogTarget::Dweb => "dweb.log",
+            LogTarget::Websocket => "websocket.log",
+            LogTarget::Backend => "backend.log",
+        }
+    }
+}
+
+impl From<Service> for LogTarget {
+    fn from(service: Service) -> Self {
+        match service {
+            Service::Ant => LogTarget::Ant,
+            Service::Anttp => LogTarget::Anttp,
+            Service::Dweb => LogTarget::Dweb,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LogSettings {
+    /// Size at which the active file is rotated.
+    pub max_file_bytes: u64,
+    /// Number of rotated files kept next to the active one.
+    pub max_files: usize,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            max_file_bytes: 5 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+impl LogSettings {
+    fn validate(&self) -> Result<(), String> {
+        if self.max_file_bytes < 64 * 1024 {
+            return Err("max_file_bytes must be at least 64 KiB".into());
+        }
+        if self.max_files == 0 || self.max_files > 50 {
+            return Err("max_files must be between 1 and 50".into());
+        }
+        Ok(())
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    // closed while rotating, Windows can't rename a file that is still open
+    file: Option<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file: Some(file),
+            size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str, settings: &LogSettings) -> std::io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > settings.max_file_bytes {
+            self.rotate(settings.max_files)?;
+        }
+        if self.file.is_none() {
+            *self = Self::open(self.path.clone())?;
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(line.as_bytes())?;
+        }
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    // backend.log -> backend.log.1 -> ... -> backend.log.<max_files>, the last one is dropped
+    fn rotate(&mut self, max_files: usize) -> std::io::Result<()> {
+        self.file = None;
+        self.size = 0;
+
+        let _ = fs::remove_file(rotated_path(&self.path, max_files));
+        for index in (1..max_files).rev() {
+            let from = rotated_path(&self.path, index);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, index + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+pub fn log_dir() -> Result<PathBuf, String> {
+    let dir = crate::get_app_data_dir()?.join("logs");
+
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create log dir: {}", e))?;
+
+    Ok(dir)
+}
+
+/// Load the persisted log settings. Called once during setup.
+pub fn init(app: &AppHandle) {
+    if let Some(saved) = settings::load::<LogSettings>(app, LOG_SETTINGS_KEY) {
+        if saved.validate().is_ok() {
+            *LOG_SETTINGS.lock().unwrap() = saved;
+        }
+    }
+}
+
+pub fn get_settings() -> LogSettings {
+    *LOG_SETTINGS.lock().unwrap()
+}
+
+pub fn set_settings(app: &AppHandle, new_settings: LogSettings) -> Result<(), String> {
+    new_settings.validate()?;
+    settings::save(app, LOG_SETTINGS_KEY, &new_settings)?;
+    *LOG_SETTINGS.lock().unwrap() = new_settings;
+    Ok(())
+}
+
+/// Append a line to the target's log file. Backend and websocket lines are
+/// also echoed to the console, as they were before they went to disk.
+pub fn write(target: LogTarget, level: &str, message: &str) {
+    let line = format!(
+        "{} [{}] {}\n",
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+        level,
+        message.trim_end()
+    );
+
+    match (target, level) {
+        (LogTarget::Backend | LogTarget::Websocket, "INFO") => print!("{}", line),
+        (LogTarget::Backend | LogTarget::Websocket, _) => eprint!("{}", line),
+        _ => {}
+    }
+
+    let settings = get_settings();
+    let mut files = LOG_FILES.lock().unwrap();
+
+    if !files.contains_key(&target) {
+        let Ok(dir) = log_dir() else {
+            return;
+        };
+        match RotatingFile::open(dir.join(target.file_name())) {
+            Ok(file) => {
+                files.insert(target, file);
+            }
+            Err(e) => {
+                eprintln!("Failed to open {} log file: {}", target.file_name(), e);
+                return;
+            }
+        }
+    }
+
+    if let Some(file) = files.get_mut(&target) {
+        if let Err(e) = file.write_line(&line, &settings) {
+            eprintln!("Failed to write {} log: {}", target.file_name(), e);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use crate::logging::LogTarget;
+use crate::settings;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tauri::AppHandle;
+
+const NETWORK_SETTINGS_KEY: &str = "network-access";
+const UNIX_SOCKET_NAME: &str = "api.sock";
+
+static NETWORK_SETTINGS: Lazy<Mutex<NetworkSettings>> =
+    Lazy::new(|| Mutex::new(NetworkSettings::default()));
+
+/// Where the websocket server listens. The sidecars stay on loopback.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", content = "address", rename_all = "lowercase")]
+pub enum BindAddress {
+    #[default]
+    Loopback,
+    All,
+    Specific(IpAddr),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    pub bind: BindAddress,
+    /// Remote addresses or CIDR ranges allowed to reach the websocket server
+    /// when it isn't bound to loopback. Local connections are always allowed.
+    /// The sidecars can't filter clients themselves, so they are never exposed.
+    pub allowlist: Vec<String>,
+    /// Also serve the local API on a unix socket only the current user can open.
+    pub unix_socket: bool,
+}
+
+impl NetworkSettings {
+    fn is_loopback(&self) -> bool {
+        match self.bind {
+            BindAddress::Loopback => true,
+            BindAddress::All => false,
+            BindAddress::Specific(ip) => ip.is_loopback(),
+        }
+    }
+}
+
+/// Load the persisted network settings. Called once during setup.
+pub fn init(app: &AppHandle) {
+    let Some(saved) = settings::load::<NetworkSettings>(app, NETWORK_SETTINGS_KEY) else {
+        return;
+    };
+
+    // a saved non-loopback bind was confirmed when it was set
+    match validate(&saved, true) {
+        Ok(()) => *NETWORK_SETTINGS.lock().unwrap() = saved,
+        Err(e) => log_warn!(
+            LogTarget::Backend,
+            "Ignoring saved network settings, staying on loopback: {}",
+            e
+        ),
+    }
+}
+
+fn validate(settings: &NetworkSettings, confirmed: bool) -> Result<(), String> {
+    for entry in &settings.allowlist {
+        parse_range(entry)?;
+    }
+
+    if settings.is_loopback() {
+        return Ok(());
+    }
+    if !confirmed {
+        return Err(
+            "Listening beyond loopback exposes SafeBox to other machines and needs confirmation"
+                .into(),
+        );
+    }
+    if settings.allowlist.is_empty() {
+        return Err("Listening beyond loopback needs at least one allowlist entry".into());
+    }
+
+    Ok(())
+}
+
+pub fn get_settings() -> NetworkSettings {
+    NETWORK_SETTINGS.lock().unwrap().clone()
+}
+
+pub fn set_settings(
+    app: &AppHandle,
+    network: NetworkSettings,
+    confirmed: bool,
+) -> Result<(), String> {
+    validate(&network, confirmed)?;
+
+    if !network.is_loopback() {
+        log_warn!(
+            LogTarget::Backend,
+            "Listening on {} for {:?}",
+            bind_ip(&network.bind),
+            network.allowlist
+        );
+    }
+
+    *NETWORK_SETTINGS.lock().unwrap() = network.clone();
+    settings::save(app, NETWORK_SETTINGS_KEY, &network)
+}
+
+fn bind_ip(bind: &BindAddress) -> IpAddr {
+    match bind {
+        BindAddress::Loopback => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        BindAddress::All => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        BindAddress::Specific(ip) => *ip,
+    }
+}
+
+/// Address for the websocket server to listen on.
+pub fn listen_addr(port: u16) -> SocketAddr {
+    SocketAddr::new(bind_ip(&NETWORK_SETTINGS.lock().unwrap().bind), port)
+}
+
+/// Whether a client connecting from `remote` may use the websocket server.
+pub fn is_allowed(remote: Option<SocketAddr>) -> bool {
+    let Some(remote) = remote else {
+        return false;
+    };
+    let ip = remote.ip().to_canonical();
+    if ip.is_loopback() {
+        return true;
+    }
+
+    let network = NETWORK_SETTINGS.lock().unwrap();
+    !network.is_loopback()
+        && network
+            .allowlist
+            .iter()
+            .filter_map(|entry| parse_range(entry).ok())
+            .any(|(net, prefix)| in_range(ip, net, prefix))
+}
+
+/// "192.168.1.20" or "192.168.1.0/24", IPv6 alike.
+fn parse_range(entry: &str) -> Result<(IpAddr, u8), String> {
+    let (ip, prefix) = match entry.split_once('/') {
+        Some((ip, prefix)) => (ip, Some(prefix)),
+        None => (entry, None),
+    };
+    let ip: IpAddr = ip
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid allowlist address '{}'", entry))?;
+
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix
+            .trim()
+            .parse::<u8>()
+            .ok()
+            .filter(|prefix| *prefix <= max)
+            .ok_or_else(|| format!("Invalid prefix length in '{}'", entry))?,
+        None => max,
+    };
+
+    Ok((ip, prefix))
+}
+
+fn in_range(ip: IpAddr, net: IpAddr, prefix: u8) -> bool {
+    match (ip, net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+/// The socket lives in a directory of its own that only we can enter, so it
+/// is never reachable with looser permissions, not even between bind and chmod.
+#[cfg(unix)]
+pub fn unix_socket_path() -> Result<PathBuf, String> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    // XDG_RUNTIME_DIR on Linux, macOS has none but a per-user TMPDIR; socket
+    // paths are limited to about 100 bytes, which rules out the app data dir
+    let base = dirs::runtime_dir().unwrap_or_else(std::env::temp_dir);
+    let dir = base.join("safebox-client");
+
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    // an existing directory keeps its mode, tighten it
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))
+        .map_err(|e| format!("Failed to restrict {}: {}", dir.display(), e))?;
+
+    Ok(dir.join(UNIX_SOCKET_NAME))
+}
+
+#[cfg(not(unix))]
+pub fn unix_socket_path() -> Result<PathBuf, String> {
+    Err("Unix sockets are not supported on this platform".into())
+}
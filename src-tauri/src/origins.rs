@@ -0,0 +1,128 @@
+use crate::logging::LogTarget;
+use crate::settings;
+use crate::types::OriginRejectedEvent;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter};
+
+const ALLOWED_ORIGINS_KEY: &str = "allowed-origins";
+
+// comma separated ids of the published SafeBox extensions, build.rs warns when unset
+const OFFICIAL_EXTENSION_IDS: Option<&str> = option_env!("SAFEBOX_EXTENSION_IDS");
+
+// a page retrying in a loop shouldn't flood the UI
+const REJECTION_NOTICE_INTERVAL: Duration = Duration::from_secs(10);
+
+static ALLOWED_ORIGINS: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(default_origins()));
+static LAST_REJECTION_NOTICE: Lazy<Mutex<HashMap<String, Instant>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub fn default_origins() -> Vec<String> {
+    OFFICIAL_EXTENSION_IDS
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| format!("chrome-extension://{}", id))
+        .collect()
+}
+
+/// Load the persisted allowlist. Called once during setup.
+pub fn init(app: &AppHandle) {
+    if default_origins().is_empty() {
+        log_warn!(
+            LogTarget::Backend,
+            "Built without SAFEBOX_EXTENSION_IDS, no extension is allowed by default"
+        );
+    }
+
+    let Some(saved) = settings::load::<Vec<String>>(app, ALLOWED_ORIGINS_KEY) else {
+        return;
+    };
+
+    match validate(&saved) {
+        Ok(()) => *ALLOWED_ORIGINS.lock().unwrap() = saved,
+        Err(e) => log_warn!(LogTarget::Backend, "Ignoring saved origin allowlist: {}", e),
+    }
+}
+
+/// Entries are exact origins like `chrome-extension://<id>` or `https://example.com:8443`.
+fn validate(origins: &[String]) -> Result<(), String> {
+    for origin in origins {
+        let Some((scheme, rest)) = origin.split_once("://") else {
+            return Err(format!("'{}' is not an origin", origin));
+        };
+        if scheme.is_empty() || rest.is_empty() || rest.contains(['/', '*', '?', '#']) {
+            return Err(format!(
+                "'{}' must be a scheme and host without path or wildcards",
+                origin
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub fn get() -> Vec<String> {
+    ALLOWED_ORIGINS.lock().unwrap().clone()
+}
+
+pub fn set(app: &AppHandle, origins: Vec<String>) -> Result<(), String> {
+    let origins: Vec<String> = origins
+        .into_iter()
+        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+    validate(&origins)?;
+
+    *ALLOWED_ORIGINS.lock().unwrap() = origins.clone();
+    settings::save(app, ALLOWED_ORIGINS_KEY, &origins)
+}
+
+/// Requests without an Origin header come from native tools, not from a
+/// web page, and are left to the other checks.
+pub fn is_allowed(origin: Option<&str>) -> bool {
+    match origin {
+        None => true,
+        Some(origin) => ALLOWED_ORIGINS
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(origin)),
+    }
+}
+
+/// Log a refused origin and tell the UI, at most once per interval and origin.
+pub fn report_rejection(app: &AppHandle, origin: &str, route: &str) {
+    log_warn!(
+        LogTarget::Websocket,
+        "Refused {} from origin {}",
+        route,
+        origin
+    );
+
+    {
+        let mut notices = LAST_REJECTION_NOTICE.lock().unwrap();
+        let now = Instant::now();
+        if notices
+            .get(origin)
+            .is_some_and(|last| now.duration_since(*last) < REJECTION_NOTICE_INTERVAL)
+        {
+            return;
+        }
+        notices.insert(origin.to_string(), now);
+    }
+
+    let _ = app.emit(
+        "origin-rejected",
+        OriginRejectedEvent {
+            origin: origin.to_string(),
+            route: route.to_string(),
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+        },
+    );
+}
@@ -0,0 +1,184 @@
+use crate::get_app_data_dir;
+use crate::logging::LogTarget;
+use crate::services::Service;
+use crate::updates;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+// sidecars spawned by this or a previous session, rewritten on every spawn and exit
+const RUNTIME_FILE: &str = "sidecars.json";
+
+static RUNTIME_FILE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+static ORPHANS: Lazy<Mutex<Vec<SidecarRecord>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SidecarRecord {
+    pub service: Service,
+    pub pid: u32,
+    pub port: u16,
+    pub exe: String,
+    /// Kernel start time of the process, guards against the pid being reused.
+    pub start_time: Option<u64>,
+}
+
+fn runtime_file_path() -> Result<PathBuf, String> {
+    Ok(get_app_data_dir()?.join(RUNTIME_FILE))
+}
+
+fn read_records() -> Vec<SidecarRecord> {
+    let Ok(path) = runtime_file_path() else {
+        return Vec::new();
+    };
+    fs::read(path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+fn write_records(records: &[SidecarRecord]) {
+    let result = runtime_file_path().and_then(|path| {
+        let json = serde_json::to_vec_pretty(records).map_err(|e| e.to_string())?;
+        fs::write(path, json).map_err(|e| e.to_string())
+    });
+
+    if let Err(e) = result {
+        log_warn!(
+            LogTarget::Backend,
+            "Failed to write {}: {}",
+            RUNTIME_FILE,
+            e
+        );
+    }
+}
+
+/// Note a freshly spawned or adopted sidecar in the runtime file.
+pub fn remember(service: Service, pid: u32, port: u16, exe: PathBuf) {
+    let _guard = RUNTIME_FILE_LOCK.lock().unwrap();
+
+    let mut records = read_records();
+    records.retain(|record| record.service != service);
+    records.push(SidecarRecord {
+        service,
+        pid,
+        port,
+        exe: exe.to_string_lossy().to_string(),
+        start_time: process_start_time(pid),
+    });
+
+    write_records(&records);
+}
+
+/// Drop a sidecar from the runtime file once it has exited or was stopped.
+pub fn forget(service: Service) {
+    let _guard = RUNTIME_FILE_LOCK.lock().unwrap();
+
+    let mut records = read_records();
+    let before = records.len();
+    records.retain(|record| record.service != service);
+
+    if records.len() != before {
+        write_records(&records);
+    }
+}
+
+/// Look for sidecars left behind by a previous session that are still alive.
+/// Runs once during setup, before anything is spawned.
+pub fn detect() -> Vec<SidecarRecord> {
+    let _guard = RUNTIME_FILE_LOCK.lock().unwrap();
+
+    let orphans: Vec<SidecarRecord> = read_records().into_iter().filter(is_ours).collect();
+
+    // keep only the live ones, so they're still known if we go down again
+    write_records(&orphans);
+
+    for orphan in &orphans {
+        log_warn!(
+            LogTarget::Backend,
+            "Found orphaned {} from a previous session (pid {}, port {})",
+            orphan.service.name(),
+            orphan.pid,
+            orphan.port
+        );
+    }
+
+    *ORPHANS.lock().unwrap() = orphans.clone();
+    orphans
+}
+
+pub fn list() -> Vec<SidecarRecord> {
+    ORPHANS.lock().unwrap().clone()
+}
+
+/// The orphan recorded for `service`, checked again so a pid that has been
+/// reused since detection is never signalled.
+pub fn verified(service: Service) -> Result<SidecarRecord, String> {
+    let record = ORPHANS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|record| record.service == service)
+        .cloned()
+        .ok_or_else(|| format!("No orphaned {} process", service.name()))?;
+
+    if !is_ours(&record) {
+        resolve(service);
+        forget(service);
+        return Err(format!(
+            "Orphaned {} (pid {}) is gone or no longer ours",
+            service.name(),
+            record.pid
+        ));
+    }
+
+    Ok(record)
+}
+
+/// Remove an orphan from the pending list once it was adopted or terminated.
+pub fn resolve(service: Service) {
+    ORPHANS
+        .lock()
+        .unwrap()
+        .retain(|record| record.service != service);
+}
+
+#[cfg(target_os = "linux")]
+fn is_ours(record: &SidecarRecord) -> bool {
+    // the bundled binary or one of the installed updates
+    if !updates::is_known_binary(record.service, std::path::Path::new(&record.exe)) {
+        return false;
+    }
+
+    let Ok(exe) = fs::read_link(format!("/proc/{}/exe", record.pid)) else {
+        return false;
+    };
+    // the link gets a " (deleted)" suffix when the binary was replaced on disk
+    let exe = exe.to_string_lossy();
+    if exe.trim_end_matches(" (deleted)") != record.exe {
+        return false;
+    }
+
+    record.start_time.is_none() || process_start_time(record.pid) == record.start_time
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_ours(_record: &SidecarRecord) -> bool {
+    // without /proc there's no way to tell our binary from a reused pid
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // the command name is in parentheses and may itself contain spaces,
+    // starttime is the 22nd field overall and the 20th after the name
+    let after_name = &stat[stat.rfind(')')? + 1..];
+    after_name.split_whitespace().nth(19)?.parse().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_start_time(_pid: u32) -> Option<u64> {
+    None
+}
@@ -0,0 +1,266 @@
+use crate::logging::LogTarget;
+use crate::settings;
+use once_cell::sync::Lazy;
+use rand::rngs::OsRng;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::AppHandle;
+use tokio::sync::watch;
+
+const PAIRED_CLIENTS_KEY: &str = "paired-clients";
+
+const CODE_LIFETIME: Duration = Duration::from_secs(5 * 60);
+// wrong guesses an address gets before it is locked out, the code itself
+// stays valid for everyone else
+const MAX_CODE_ATTEMPTS: u32 = 5;
+const LOCKOUT: Duration = Duration::from_secs(5 * 60);
+
+static PENDING_CODE: Lazy<Mutex<Option<PendingCode>>> = Lazy::new(|| Mutex::new(None));
+static PAIRED_CLIENTS: Lazy<Mutex<Vec<StoredClient>>> = Lazy::new(|| Mutex::new(Vec::new()));
+// per remote address, `None` for the unix socket
+static WRONG_GUESSES: Lazy<Mutex<HashMap<Option<IpAddr>, WrongGuesses>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// per client, set to true when it is revoked so its open connections close
+static REVOCATIONS: Lazy<Mutex<HashMap<String, watch::Sender<bool>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+struct PendingCode {
+    code: String,
+    expires: Instant,
+}
+
+struct WrongGuesses {
+    count: u32,
+    last: Instant,
+}
+
+/// A browser extension or tool that exchanged a pairing code for a token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairedClient {
+    pub id: String,
+    pub name: String,
+    pub paired_at_ms: u64,
+    pub last_seen_ms: Option<u64>,
+}
+
+// only the token's hash is kept, and never handed to the UI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredClient {
+    #[serde(flatten)]
+    client: PairedClient,
+    token_hash: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PairingCode {
+    pub code: String,
+    pub expires_in_secs: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PairRequest {
+    pub code: String,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PairResponse {
+    pub client_id: String,
+    pub token: String,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Load the paired clients. Called once during setup.
+pub fn init(app: &AppHandle) {
+    if let Some(saved) = settings::load::<Vec<StoredClient>>(app, PAIRED_CLIENTS_KEY) {
+        *PAIRED_CLIENTS.lock().unwrap() = saved;
+    }
+}
+
+fn save(app: &AppHandle) -> Result<(), String> {
+    let clients = PAIRED_CLIENTS.lock().unwrap().clone();
+    settings::save(app, PAIRED_CLIENTS_KEY, &clients)
+}
+
+/// A fresh one-time code for the user to type into the extension. Replaces
+/// any code that is still pending.
+pub fn start() -> PairingCode {
+    let code = format!("{:08}", OsRng.gen_range(0..100_000_000u32));
+
+    *PENDING_CODE.lock().unwrap() = Some(PendingCode {
+        code: code.clone(),
+        expires: Instant::now() + CODE_LIFETIME,
+    });
+    // a fresh code lets a locked out extension try again
+    WRONG_GUESSES.lock().unwrap().clear();
+
+    PairingCode {
+        code,
+        expires_in_secs: CODE_LIFETIME.as_secs(),
+    }
+}
+
+fn is_locked_out(remote: Option<IpAddr>) -> bool {
+    let mut guesses = WRONG_GUESSES.lock().unwrap();
+    guesses.retain(|_, wrong| wrong.last.elapsed() < LOCKOUT);
+    guesses
+        .get(&remote)
+        .is_some_and(|wrong| wrong.count >= MAX_CODE_ATTEMPTS)
+}
+
+fn record_wrong_guess(remote: Option<IpAddr>) {
+    let mut guesses = WRONG_GUESSES.lock().unwrap();
+    let wrong = guesses.entry(remote).or_insert(WrongGuesses {
+        count: 0,
+        last: Instant::now(),
+    });
+    wrong.count += 1;
+    wrong.last = Instant::now();
+
+    if wrong.count == MAX_CODE_ATTEMPTS {
+        let remote = remote.map_or("the unix socket".to_string(), |ip| ip.to_string());
+        log_warn!(
+            LogTarget::Websocket,
+            "Pairing locked for {} after {} wrong attempts",
+            remote,
+            MAX_CODE_ATTEMPTS
+        );
+    }
+}
+
+/// Trade the pending code for a long-lived token. The code works once.
+/// `remote` is the address the request came from, wrong guesses lock it out
+/// for a while.
+pub fn pair(
+    app: &AppHandle,
+    remote: Option<IpAddr>,
+    request: PairRequest,
+) -> Result<PairResponse, String> {
+    if is_locked_out(remote) {
+        return Err("Too many wrong pairing codes, try again later".into());
+    }
+
+    {
+        let mut pending = PENDING_CODE.lock().unwrap();
+        let Some(code) = pending.as_mut() else {
+            return Err("No pairing in progress".into());
+        };
+
+        if Instant::now() > code.expires {
+            *pending = None;
+            return Err("Pairing code expired".into());
+        }
+
+        if !constant_time_eq(code.code.as_bytes(), request.code.trim().as_bytes()) {
+            record_wrong_guess(remote);
+            return Err("Wrong pairing code".into());
+        }
+
+        *pending = None;
+    }
+
+    let mut token_bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut token_bytes);
+    let token = hex::encode(token_bytes);
+
+    let mut id_bytes = [0u8; 8];
+    OsRng.fill_bytes(&mut id_bytes);
+
+    let client = PairedClient {
+        id: hex::encode(id_bytes),
+        name: request
+            .name
+            .map(|name| name.trim().chars().take(64).collect::<String>())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "Unnamed client".to_string()),
+        paired_at_ms: now_ms(),
+        last_seen_ms: None,
+    };
+    log_info!(
+        LogTarget::Websocket,
+        "Paired {} ({})",
+        client.name,
+        client.id
+    );
+
+    let client_id = client.id.clone();
+    PAIRED_CLIENTS.lock().unwrap().push(StoredClient {
+        client,
+        token_hash: hash_token(&token),
+    });
+    save(app)?;
+
+    Ok(PairResponse { client_id, token })
+}
+
+/// The client a token belongs to, if it is still paired.
+pub fn authenticate(token: &str) -> Option<PairedClient> {
+    let token_hash = hash_token(token);
+    let mut clients = PAIRED_CLIENTS.lock().unwrap();
+    let stored = clients
+        .iter_mut()
+        .find(|stored| stored.token_hash == token_hash)?;
+    stored.client.last_seen_ms = Some(now_ms());
+    Some(stored.client.clone())
+}
+
+pub fn list() -> Vec<PairedClient> {
+    PAIRED_CLIENTS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|stored| stored.client.clone())
+        .collect()
+}
+
+/// Turns true once the client is revoked. `None` if it already was, so a
+/// connection authenticated just before can't outlive the revocation.
+pub fn revocation(client_id: &str) -> Option<watch::Receiver<bool>> {
+    let clients = PAIRED_CLIENTS.lock().unwrap();
+    if !clients.iter().any(|stored| stored.client.id == client_id) {
+        return None;
+    }
+
+    let mut revocations = REVOCATIONS.lock().unwrap();
+    let revoked = revocations
+        .entry(client_id.to_string())
+        .or_insert_with(|| watch::channel(false).0);
+    Some(revoked.subscribe())
+}
+
+/// Forget a client. Its token stops working and its open connections close.
+pub fn revoke(app: &AppHandle, id: &str) -> Result<(), String> {
+    {
+        let mut clients = PAIRED_CLIENTS.lock().unwrap();
+        let before = clients.len();
+        clients.retain(|stored| stored.client.id != id);
+        if clients.len() == before {
+            return Err(format!("No paired client {}", id));
+        }
+
+        if let Some(revoked) = REVOCATIONS.lock().unwrap().remove(id) {
+            revoked.send_replace(true);
+        }
+    }
+    log_info!(LogTarget::Websocket, "Revoked paired client {}", id);
+    save(app)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,247 @@
+use serde::Serialize;
+
+/// A process holding one of our ports, shown to the user before anything is killed.
+#[derive(Debug, Clone, Serialize)]
+pub struct PortOwner {
+    pub port: u16,
+    /// `None` when the socket belongs to a process we aren't allowed to look into.
+    pub pid: Option<u32>,
+    pub name: Option<String>,
+    pub exe: Option<String>,
+    pub uid: Option<u32>,
+    pub user: Option<String>,
+    /// Runs as the same user as SafeBox, so it may be signalled.
+    pub killable: bool,
+}
+
+/// Everything listening on `port`, on IPv4 or IPv6.
+#[cfg(target_os = "linux")]
+pub fn find(port: u16) -> Result<Vec<PortOwner>, String> {
+    use std::collections::HashMap;
+
+    let mut sockets: Vec<(u64, u32)> = Vec::new();
+    for table in ["/proc/net/tcp", "/proc/net/tcp6"] {
+        // tcp6 is missing when IPv6 is disabled
+        if let Ok(contents) = std::fs::read_to_string(table) {
+            sockets.extend(listening_sockets(&contents, port));
+        }
+    }
+    if sockets.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut pids_by_inode: HashMap<u64, Vec<u32>> = HashMap::new();
+    let inodes: Vec<u64> = sockets.iter().map(|(inode, _)| *inode).collect();
+    for (pid, inode) in socket_inodes_by_pid() {
+        if inodes.contains(&inode) {
+            let pids = pids_by_inode.entry(inode).or_default();
+            if !pids.contains(&pid) {
+                pids.push(pid);
+            }
+        }
+    }
+
+    let own_uid = current_uid();
+    let mut owners: Vec<PortOwner> = Vec::new();
+    for (inode, socket_uid) in sockets {
+        let Some(pids) = pids_by_inode.get(&inode) else {
+            // the fds of other users' processes can't be read, all we know is the uid
+            owners.push(PortOwner {
+                port,
+                pid: None,
+                name: None,
+                exe: None,
+                uid: Some(socket_uid),
+                user: user_name(socket_uid),
+                killable: false,
+            });
+            continue;
+        };
+
+        for &pid in pids {
+            // a dual-stack listener shows up in both tables
+            if owners.iter().any(|owner| owner.pid == Some(pid)) {
+                continue;
+            }
+            let uid = process_uid(pid);
+            owners.push(PortOwner {
+                port,
+                pid: Some(pid),
+                name: std::fs::read_to_string(format!("/proc/{}/comm", pid))
+                    .ok()
+                    .map(|comm| comm.trim_end().to_string()),
+                exe: std::fs::read_link(format!("/proc/{}/exe", pid))
+                    .ok()
+                    .map(|exe| exe.to_string_lossy().to_string()),
+                uid,
+                user: uid.and_then(user_name),
+                killable: uid == Some(own_uid) && pid != std::process::id(),
+            });
+        }
+    }
+
+    Ok(owners)
+}
+
+/// `(inode, uid)` of every socket listening on `port` in a /proc/net/tcp* table.
+#[cfg(target_os = "linux")]
+fn listening_sockets(table: &str, port: u16) -> Vec<(u64, u32)> {
+    const TCP_LISTEN: &str = "0A";
+
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            // sl local_address rem_address st tx:rx tr:when retrnsmt uid timeout inode
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 || fields[3] != TCP_LISTEN {
+                return None;
+            }
+            let local_port = fields[1].rsplit(':').next()?;
+            if u16::from_str_radix(local_port, 16).ok()? != port {
+                return None;
+            }
+            let inode: u64 = fields[9].parse().ok()?;
+            (inode != 0).then_some((inode, fields[7].parse().ok()?))
+        })
+        .collect()
+}
+
+/// `(pid, inode)` for every socket fd we're allowed to see.
+#[cfg(target_os = "linux")]
+fn socket_inodes_by_pid() -> Vec<(u32, u64)> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+
+    let mut found = Vec::new();
+    for entry in entries.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let Ok(target) = std::fs::read_link(fd.path()) else {
+                continue;
+            };
+            let target = target.to_string_lossy();
+            if let Some(inode) = target
+                .strip_prefix("socket:[")
+                .and_then(|rest| rest.strip_suffix(']'))
+                .and_then(|inode| inode.parse().ok())
+            {
+                found.push((pid, inode));
+            }
+        }
+    }
+    found
+}
+
+#[cfg(target_os = "linux")]
+fn process_uid(pid: u32) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(format!("/proc/{}", pid))
+        .ok()
+        .map(|metadata| metadata.uid())
+}
+
+#[cfg(target_os = "linux")]
+fn user_name(uid: u32) -> Option<String> {
+    let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        let entry_uid: u32 = fields.nth(1)?.parse().ok()?;
+        (entry_uid == uid).then(|| name.to_string())
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn current_uid() -> u32 {
+    // SAFETY: geteuid can't fail
+    unsafe { libc::geteuid() }
+}
+
+/// Without /proc only the pids are known, the OS refuses a kill we may not do.
+#[cfg(not(target_os = "linux"))]
+pub fn find(port: u16) -> Result<Vec<PortOwner>, String> {
+    use std::process::Command;
+
+    #[cfg(target_os = "windows")]
+    let pids: Vec<u32> = {
+        let output = Command::new("netstat")
+            .args(["-ano", "-p", "TCP"])
+            .output()
+            .map_err(|e| format!("Failed to run netstat: {}", e))?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let listening = fields.get(3) == Some(&"LISTENING");
+                let local_port = fields.get(1)?.rsplit(':').next()?.parse::<u16>().ok()?;
+                if listening && local_port == port {
+                    fields.get(4)?.parse().ok()
+                } else {
+                    None
+                }
+            })
+            .collect()
+    };
+
+    #[cfg(not(target_os = "windows"))]
+    let pids: Vec<u32> = {
+        let output = Command::new("lsof")
+            .args(["-t", "-sTCP:LISTEN", "-i", &format!("TCP:{}", port)])
+            .output()
+            .map_err(|e| format!("Failed to run lsof: {}", e))?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.trim().parse().ok())
+            .collect()
+    };
+
+    let mut owners: Vec<PortOwner> = Vec::new();
+    for pid in pids {
+        if owners.iter().any(|owner| owner.pid == Some(pid)) {
+            continue;
+        }
+        owners.push(PortOwner {
+            port,
+            pid: Some(pid),
+            name: None,
+            exe: None,
+            uid: None,
+            user: None,
+            killable: pid != std::process::id(),
+        });
+    }
+    Ok(owners)
+}
+
+/// SIGTERM first and SIGKILL once the shutdown grace period is over.
+#[cfg(unix)]
+pub async fn kill(pid: u32) -> crate::services::ShutdownOutcome {
+    crate::services::terminate_pid(pid, crate::services::shutdown_grace_period()).await
+}
+
+#[cfg(not(unix))]
+pub async fn kill(pid: u32) -> crate::services::ShutdownOutcome {
+    use crate::services::ShutdownOutcome;
+
+    match std::process::Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/T", "/F"])
+        .output()
+    {
+        Ok(output) if output.status.success() => ShutdownOutcome::Killed,
+        Ok(output) => {
+            ShutdownOutcome::Failed(String::from_utf8_lossy(&output.stderr).trim().to_string())
+        }
+        Err(e) => ShutdownOutcome::Failed(format!("Failed to run taskkill: {}", e)),
+    }
+}
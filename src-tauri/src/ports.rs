@@ -0,0 +1,367 @@
+use crate::logging::LogTarget;
+use crate::network;
+use crate::orphans;
+use crate::port_owners::{self, PortOwner};
+use crate::services::{self, Service};
+use crate::settings;
+use crate::updates;
+use crate::{ANTTP_PORT, ANT_PORT, DWEB_PORT, WEBSOCKET_PORT};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::sync::Mutex;
+use tauri::AppHandle;
+
+const PORT_ALLOCATION_KEY: &str = "port-allocation";
+const PORTS_KEY: &str = "ports";
+
+static PORT_ALLOCATION: Lazy<Mutex<PortAllocation>> =
+    Lazy::new(|| Mutex::new(PortAllocation::default()));
+
+// what the user asked for, auto-allocated fallbacks are never persisted
+static CONFIGURED_PORTS: Lazy<Mutex<HashMap<ManagedPort, u16>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The four ports SafeBox listens on or hands to its sidecars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ManagedPort {
+    Ant,
+    Anttp,
+    Dweb,
+    Websocket,
+}
+
+impl ManagedPort {
+    pub const ALL: [ManagedPort; 4] = [
+        ManagedPort::Ant,
+        ManagedPort::Anttp,
+        ManagedPort::Dweb,
+        ManagedPort::Websocket,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ManagedPort::Ant => "ant",
+            ManagedPort::Anttp => "anttp",
+            ManagedPort::Dweb => "dweb",
+            ManagedPort::Websocket => "websocket",
+        }
+    }
+
+    fn slot(self) -> &'static Mutex<u16> {
+        match self {
+            ManagedPort::Ant => &ANT_PORT,
+            ManagedPort::Anttp => &ANTTP_PORT,
+            ManagedPort::Dweb => &DWEB_PORT,
+            ManagedPort::Websocket => &WEBSOCKET_PORT,
+        }
+    }
+
+    pub fn get(self) -> u16 {
+        *self.slot().lock().unwrap()
+    }
+
+    pub fn set(self, port: u16) {
+        *self.slot().lock().unwrap() = port;
+    }
+
+    pub fn service(self) -> Option<Service> {
+        match self {
+            ManagedPort::Ant => Some(Service::Ant),
+            ManagedPort::Anttp => Some(Service::Anttp),
+            ManagedPort::Dweb => Some(Service::Dweb),
+            ManagedPort::Websocket => None,
+        }
+    }
+}
+
+impl From<Service> for ManagedPort {
+    fn from(service: Service) -> Self {
+        match service {
+            Service::Ant => ManagedPort::Ant,
+            Service::Anttp => ManagedPort::Anttp,
+            Service::Dweb => ManagedPort::Dweb,
+        }
+    }
+}
+
+/// Opt-in fallback to another port when the configured one is taken.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PortAllocation {
+    pub auto: bool,
+    pub range_start: u16,
+    pub range_end: u16,
+}
+
+impl Default for PortAllocation {
+    fn default() -> Self {
+        Self {
+            auto: false,
+            range_start: 20000,
+            range_end: 20999,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortReassignment {
+    pub port: ManagedPort,
+    pub requested: u16,
+    pub actual: u16,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortDiagnosis {
+    pub port: ManagedPort,
+    pub number: u16,
+    pub in_use: bool,
+    pub owners: Vec<PortOwner>,
+    /// Held by what this session runs on the port, which is expected.
+    pub ours: bool,
+    /// Held by a SafeBox client or sidecar this session doesn't manage.
+    pub stale_instance: bool,
+    pub hint: Option<String>,
+}
+
+/// Sidecars listen on loopback and the websocket server on the bind address,
+/// a port must be free on both.
+pub fn is_port_in_use(port: u16) -> bool {
+    !can_bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port))
+        || !can_bind(network::listen_addr(port))
+}
+
+fn can_bind(addr: SocketAddr) -> bool {
+    // the listener is closed again when this returns
+    TcpListener::bind(addr).is_ok()
+}
+
+/// Load the persisted ports and allocation settings. Called once during
+/// setup, before the websocket server binds.
+pub fn init(app: &AppHandle) {
+    if let Some(saved) = settings::load::<PortAllocation>(app, PORT_ALLOCATION_KEY) {
+        match validate_allocation(&saved) {
+            Ok(()) => *PORT_ALLOCATION.lock().unwrap() = saved,
+            Err(e) => log_warn!(LogTarget::Backend, "Ignoring saved port allocation: {}", e),
+        }
+    }
+
+    let Some(saved) = settings::load::<HashMap<ManagedPort, u16>>(app, PORTS_KEY) else {
+        return;
+    };
+
+    // checked as a whole, a saved port may well take over another one's default
+    let merged: Vec<u16> = ManagedPort::ALL
+        .into_iter()
+        .map(|port| saved.get(&port).copied().unwrap_or_else(|| port.get()))
+        .collect();
+    let conflict = merged
+        .iter()
+        .enumerate()
+        .any(|(i, value)| *value == 0 || merged[..i].contains(value));
+    if conflict {
+        log_warn!(
+            LogTarget::Backend,
+            "Ignoring saved ports {:?}: invalid or conflicting",
+            saved
+        );
+        return;
+    }
+
+    for (port, value) in &saved {
+        port.set(*value);
+    }
+    *CONFIGURED_PORTS.lock().unwrap() = saved;
+}
+
+/// Check `value` can be used for `port` without clashing with the others.
+pub fn validate(port: ManagedPort, value: u16) -> Result<(), String> {
+    if value == 0 {
+        return Err("Port must be > 0".into());
+    }
+
+    if let Some(other) = ManagedPort::ALL
+        .into_iter()
+        .find(|other| *other != port && other.get() == value)
+    {
+        return Err(format!(
+            "Port {} is already used for {}",
+            value,
+            other.name()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Persist a port the user configured.
+pub fn save(app: &AppHandle, port: ManagedPort, value: u16) -> Result<(), String> {
+    let configured = {
+        let mut configured = CONFIGURED_PORTS.lock().unwrap();
+        configured.insert(port, value);
+        configured.clone()
+    };
+
+    settings::save(app, PORTS_KEY, &configured)
+}
+
+fn validate_allocation(allocation: &PortAllocation) -> Result<(), String> {
+    if allocation.range_start == 0 {
+        return Err("Port range must start above 0".into());
+    }
+    if allocation.range_start > allocation.range_end {
+        return Err(format!(
+            "Port range {}-{} is empty",
+            allocation.range_start, allocation.range_end
+        ));
+    }
+    Ok(())
+}
+
+pub fn get_allocation() -> PortAllocation {
+    PORT_ALLOCATION.lock().unwrap().clone()
+}
+
+pub fn set_allocation(app: &AppHandle, allocation: PortAllocation) -> Result<(), String> {
+    validate_allocation(&allocation)?;
+    *PORT_ALLOCATION.lock().unwrap() = allocation.clone();
+    settings::save(app, PORT_ALLOCATION_KEY, &allocation)
+}
+
+/// Make sure `port` can be bound before something is started on it. With
+/// auto allocation on, a taken port is moved to the first free one in the
+/// configured range that no other managed port uses.
+pub fn ensure_free(port: ManagedPort) -> Result<Option<PortReassignment>, String> {
+    let requested = port.get();
+    if !is_port_in_use(requested) {
+        return Ok(None);
+    }
+
+    let allocation = get_allocation();
+    if !allocation.auto {
+        return Err(format!("Port {} is already in use", requested));
+    }
+
+    let others: Vec<u16> = ManagedPort::ALL
+        .into_iter()
+        .filter(|other| *other != port)
+        .map(ManagedPort::get)
+        .collect();
+
+    let actual = (allocation.range_start..=allocation.range_end)
+        .find(|candidate| !others.contains(candidate) && !is_port_in_use(*candidate))
+        .ok_or_else(|| {
+            format!(
+                "Port {} is already in use and no free port is left in {}-{}",
+                requested, allocation.range_start, allocation.range_end
+            )
+        })?;
+
+    port.set(actual);
+    log_info!(
+        LogTarget::Backend,
+        "Port {} for {} is in use, using {} instead",
+        requested,
+        port.name(),
+        actual
+    );
+
+    Ok(Some(PortReassignment {
+        port,
+        requested,
+        actual,
+    }))
+}
+
+/// Who is holding each of the managed ports, and what the user can do about it.
+pub fn diagnose() -> Vec<PortDiagnosis> {
+    ManagedPort::ALL.into_iter().map(diagnose_port).collect()
+}
+
+fn diagnose_port(port: ManagedPort) -> PortDiagnosis {
+    let number = port.get();
+    let in_use = is_port_in_use(number);
+    let owners = if in_use {
+        port_owners::find(number).unwrap_or_else(|e| {
+            log_warn!(
+                LogTarget::Backend,
+                "Failed to look up owner of port {}: {}",
+                number,
+                e
+            );
+            Vec::new()
+        })
+    } else {
+        Vec::new()
+    };
+
+    let expected_pid = match port.service() {
+        Some(service) => services::pid(service),
+        None => Some(std::process::id()),
+    };
+    let ours = owners
+        .iter()
+        .any(|owner| owner.pid.is_some() && owner.pid == expected_pid);
+    let stale = owners
+        .iter()
+        .find(|owner| owner.pid != expected_pid && is_safebox_process(owner));
+
+    let hint = if !in_use || ours {
+        None
+    } else if let Some(stale) = stale {
+        let pid = stale.pid.unwrap_or_default();
+        if orphans::list().iter().any(|orphan| orphan.pid == pid) {
+            Some(format!(
+                "Left running by an earlier SafeBox session (pid {}). Adopt or terminate it above.",
+                pid
+            ))
+        } else {
+            Some(format!(
+                "Held by another SafeBox instance (pid {}). Close it or kill it from here.",
+                pid
+            ))
+        }
+    } else {
+        Some(match owners.first() {
+            Some(owner) if owner.killable => format!(
+                "Used by {} (pid {}). Stop it or pick another port.",
+                owner.name.as_deref().unwrap_or("another program"),
+                owner.pid.unwrap_or_default()
+            ),
+            Some(owner) => format!(
+                "Used by a process of {}. Pick another port or turn on automatic port fallback.",
+                owner.user.as_deref().unwrap_or("another user")
+            ),
+            None => "Used by a process SafeBox can't identify. Pick another port or turn on \
+                automatic port fallback."
+                .to_string(),
+        })
+    };
+
+    let stale_instance = stale.is_some();
+    PortDiagnosis {
+        port,
+        number,
+        in_use,
+        owners,
+        ours,
+        stale_instance,
+        hint,
+    }
+}
+
+/// A SafeBox client or one of the sidecar binaries it runs.
+fn is_safebox_process(owner: &PortOwner) -> bool {
+    let Some(exe) = &owner.exe else {
+        return false;
+    };
+    // the link gets a " (deleted)" suffix once an update replaced the binary
+    let exe = std::path::Path::new(exe.trim_end_matches(" (deleted)"));
+
+    std::env::current_exe().is_ok_and(|current| current == exe)
+        || Service::ALL
+            .into_iter()
+            .any(|service| updates::is_known_binary(service, exe))
+}
@@ -0,0 +1,281 @@
+//! Messages on the websocket routes. Since version 2 every JSON message in
+//! either direction is an object whose `type` field names the variant.
+//! Binary upload chunks are described in `upload_frames`.
+
+use crate::events::{PushEvent, Topic};
+use crate::quotas::QuotaLimit;
+use crate::services::Service;
+use crate::updates;
+use crate::upload_frames::ChunkFormat;
+use schemars::{schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use tauri::AppHandle;
+
+/// Bumped on any change an older client could misread. Version 1 is the
+/// original protocol without a hello, still spoken by older extensions.
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
+/// What this server can do beyond protocol version 1.
+pub const FEATURES: &[&str] = &["binary_chunks", "resume", "events", "download"];
+
+/// Everything a client may send.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// The answer to the server hello, sent first.
+    Hello(ClientHello),
+    /// An upload chunk on a connection using JSON chunks.
+    Chunk(JsonChunk),
+    /// Asks which chunks of an interrupted upload are already here.
+    Resume(ResumeRequest),
+    Download(DownloadRequest),
+    /// Start pushing events of these topics, on the root socket.
+    Subscribe(TopicsRequest),
+    Unsubscribe(TopicsRequest),
+}
+
+impl ClientMessage {
+    /// The `type` of the message, for error replies.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClientMessage::Hello(_) => "hello",
+            ClientMessage::Chunk(_) => "chunk",
+            ClientMessage::Resume(_) => "resume",
+            ClientMessage::Download(_) => "download",
+            ClientMessage::Subscribe(_) => "subscribe",
+            ClientMessage::Unsubscribe(_) => "unsubscribe",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ClientHello {
+    pub protocol_version: u32,
+    pub client_version: Option<String>,
+    /// Overrides the `?format=` picked on the upload socket.
+    pub chunk_format: Option<ChunkFormat>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct JsonChunk {
+    pub metadata: ChunkMetadata,
+    /// The chunk bytes, base64 encoded.
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ChunkMetadata {
+    pub filename: String,
+    pub mime_type: String,
+    pub chunk_index: usize,
+    pub total_chunks: usize,
+    pub upload_id: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ResumeRequest {
+    pub upload_id: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DownloadRequest {
+    pub xorname: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TopicsRequest {
+    pub topics: Vec<Topic>,
+}
+
+/// Everything the server may send.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// Sent as soon as a websocket is open.
+    Hello(ServerHello),
+    /// The client hello was accepted.
+    Ready {
+        protocol_version: u32,
+        chunk_format: ChunkFormat,
+    },
+    ChunkReceived {
+        upload_id: String,
+        chunk_index: usize,
+    },
+    UploadComplete {
+        upload_id: String,
+        xorname: String,
+    },
+    UploadError {
+        upload_id: Option<String>,
+        error: String,
+    },
+    /// The client ran into one of its upload limits, `allowed` being the
+    /// configured value. The chunk or upload was not accepted.
+    QuotaExceeded {
+        upload_id: Option<String>,
+        limit: QuotaLimit,
+        allowed: u64,
+        error: String,
+    },
+    ResumeState {
+        upload_id: String,
+        /// `None` when nothing of the upload is stored.
+        total_chunks: Option<usize>,
+        received: Vec<usize>,
+    },
+    /// The file is on disk at `path`.
+    DownloadComplete {
+        xorname: String,
+        path: String,
+    },
+    DownloadError {
+        error: String,
+    },
+    /// Every topic the connection is subscribed to now.
+    Subscribed {
+        topics: Vec<Topic>,
+    },
+    Event(PushEvent),
+    /// A message that isn't about a particular upload or download failed.
+    Error {
+        code: ErrorCode,
+        error: String,
+    },
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ServerHello {
+    pub protocol_version: u32,
+    pub min_protocol_version: u32,
+    pub app_version: String,
+    /// What each sidecar reports for `--version`, or the selected update.
+    pub sidecars: HashMap<Service, String>,
+    pub features: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    IncompatibleProtocol,
+    /// Not valid JSON, an unknown `type`, or a missing or misspelled field.
+    InvalidMessage,
+    /// A valid message that doesn't belong on this socket or at this point.
+    UnexpectedMessage,
+    /// The connection fell too far behind and missed events.
+    EventsLost,
+}
+
+impl ServerMessage {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Version 1 clients expect errors keyed by `action` or a bare `error`.
+    fn to_legacy_json(&self) -> String {
+        match self {
+            ServerMessage::UploadError { upload_id, error }
+            | ServerMessage::QuotaExceeded {
+                upload_id, error, ..
+            } => json!({
+                "action": "uploadError",
+                "upload_id": upload_id,
+                "error": error,
+            })
+            .to_string(),
+            ServerMessage::DownloadError { error } | ServerMessage::Error { error, .. } => {
+                json!({ "error": error }).to_string()
+            }
+            _ => self.to_json(),
+        }
+    }
+}
+
+/// The chunk of protocol version 1, whose `type` was never checked.
+#[derive(Debug, Deserialize)]
+pub struct LegacyChunk {
+    pub metadata: ChunkMetadata,
+    pub data: String,
+}
+
+/// The download request of protocol version 1.
+#[derive(Debug, Deserialize)]
+pub struct LegacyDownloadRequest {
+    pub action: String,
+    pub xorname: String,
+}
+
+/// What was agreed on for one connection.
+#[derive(Debug, Clone, Copy)]
+pub struct Session {
+    pub protocol_version: u32,
+    pub chunk_format: ChunkFormat,
+}
+
+impl Session {
+    pub fn legacy(chunk_format: ChunkFormat) -> Self {
+        Session {
+            protocol_version: LEGACY_PROTOCOL_VERSION,
+            chunk_format,
+        }
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.protocol_version == LEGACY_PROTOCOL_VERSION
+    }
+
+    pub fn encode(&self, message: &ServerMessage) -> String {
+        if self.is_legacy() {
+            message.to_legacy_json()
+        } else {
+            message.to_json()
+        }
+    }
+}
+
+pub fn server_hello(app: &AppHandle) -> ServerMessage {
+    ServerMessage::Hello(ServerHello {
+        protocol_version: PROTOCOL_VERSION,
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        app_version: app.package_info().version.to_string(),
+        sidecars: Service::ALL
+            .into_iter()
+            .map(|service| (service, updates::active_version(service)))
+            .collect(),
+        features: FEATURES.iter().map(|feature| feature.to_string()).collect(),
+    })
+}
+
+/// Check a client hello against what we speak.
+pub fn negotiate(hello: &ClientHello, default_format: ChunkFormat) -> Result<Session, String> {
+    if hello.protocol_version < MIN_PROTOCOL_VERSION || hello.protocol_version > PROTOCOL_VERSION {
+        return Err(format!(
+            "Protocol version {} is not supported, this SafeBox client speaks {} to {}",
+            hello.protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        ));
+    }
+
+    Ok(Session {
+        protocol_version: hello.protocol_version,
+        chunk_format: hello.chunk_format.unwrap_or(default_format),
+    })
+}
+
+/// JSON Schemas of both directions, for clients to validate against.
+pub fn schema() -> serde_json::Value {
+    json!({
+        "protocol_version": PROTOCOL_VERSION,
+        "client": schema_for!(ClientMessage),
+        "server": schema_for!(ServerMessage),
+    })
+}
@@ -0,0 +1,372 @@
+use crate::logging::LogTarget;
+use crate::pairing::PairedClient;
+use crate::settings;
+use crate::types::QuotaExceededEvent;
+use once_cell::sync::Lazy;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter};
+
+const UPLOAD_QUOTAS_KEY: &str = "upload-quotas";
+
+const RATE_WINDOW: Duration = Duration::from_secs(60 * 60);
+// a client retrying in a loop shouldn't flood the UI
+const EXCEEDED_NOTICE_INTERVAL: Duration = Duration::from_secs(10);
+
+static UPLOAD_QUOTAS: Lazy<Mutex<UploadQuotas>> = Lazy::new(|| Mutex::new(UploadQuotas::default()));
+static UPLOAD_STARTS: Lazy<Mutex<HashMap<String, VecDeque<Instant>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static LAST_EXCEEDED_NOTICE: Lazy<Mutex<HashMap<(String, QuotaLimit), Instant>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Limits applied to each paired client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadQuotas {
+    pub max_upload_bytes: u64,
+    /// Unfinished uploads on one upload socket.
+    pub max_concurrent_uploads: usize,
+    /// Chunks held in memory for all of a client's unfinished uploads.
+    pub max_in_flight_bytes: u64,
+    pub max_uploads_per_hour: usize,
+}
+
+impl Default for UploadQuotas {
+    fn default() -> Self {
+        Self {
+            max_upload_bytes: 2 * 1024 * 1024 * 1024,
+            max_concurrent_uploads: 4,
+            max_in_flight_bytes: 1024 * 1024 * 1024,
+            max_uploads_per_hour: 120,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaLimit {
+    UploadBytes,
+    ConcurrentUploads,
+    InFlightBytes,
+    UploadsPerHour,
+}
+
+/// A limit a client ran into, `allowed` being its configured value.
+#[derive(Debug, Clone)]
+pub struct QuotaExceeded {
+    pub limit: QuotaLimit,
+    pub allowed: u64,
+    pub error: String,
+}
+
+impl QuotaExceeded {
+    fn new(limit: QuotaLimit, allowed: u64, error: String) -> Self {
+        QuotaExceeded {
+            limit,
+            allowed,
+            error,
+        }
+    }
+}
+
+/// Load the persisted quotas. Called once during setup.
+pub fn init(app: &AppHandle) {
+    let Some(saved) = settings::load::<UploadQuotas>(app, UPLOAD_QUOTAS_KEY) else {
+        return;
+    };
+
+    match validate(&saved) {
+        Ok(()) => *UPLOAD_QUOTAS.lock().unwrap() = saved,
+        Err(e) => log_warn!(LogTarget::Backend, "Ignoring saved upload quotas: {}", e),
+    }
+}
+
+fn validate(quotas: &UploadQuotas) -> Result<(), String> {
+    if quotas.max_upload_bytes == 0
+        || quotas.max_concurrent_uploads == 0
+        || quotas.max_in_flight_bytes == 0
+        || quotas.max_uploads_per_hour == 0
+    {
+        return Err("Upload limits must be above zero".into());
+    }
+    Ok(())
+}
+
+pub fn get() -> UploadQuotas {
+    UPLOAD_QUOTAS.lock().unwrap().clone()
+}
+
+pub fn set(app: &AppHandle, quotas: UploadQuotas) -> Result<(), String> {
+    validate(&quotas)?;
+    *UPLOAD_QUOTAS.lock().unwrap() = quotas.clone();
+    settings::save(app, UPLOAD_QUOTAS_KEY, &quotas)
+}
+
+pub fn check_upload_bytes(bytes: u64) -> Result<(), QuotaExceeded> {
+    let allowed = get().max_upload_bytes;
+    if bytes > allowed {
+        return Err(QuotaExceeded::new(
+            QuotaLimit::UploadBytes,
+            allowed,
+            format!("Uploads are limited to {} bytes", allowed),
+        ));
+    }
+    Ok(())
+}
+
+fn check_concurrent_uploads(running: usize) -> Result<(), QuotaExceeded> {
+    let allowed = get().max_concurrent_uploads;
+    if running >= allowed {
+        return Err(QuotaExceeded::new(
+            QuotaLimit::ConcurrentUploads,
+            allowed as u64,
+            format!("At most {} uploads may run at once", allowed),
+        ));
+    }
+    Ok(())
+}
+
+fn check_in_flight_bytes(bytes: u64) -> Result<(), QuotaExceeded> {
+    let allowed = get().max_in_flight_bytes;
+    if bytes > allowed {
+        return Err(QuotaExceeded::new(
+            QuotaLimit::InFlightBytes,
+            allowed,
+            format!("At most {} bytes may wait for their upload", allowed),
+        ));
+    }
+    Ok(())
+}
+
+/// What a websocket upload holds before an incoming chunk is stored.
+#[derive(Debug, Default)]
+pub struct ChunkUsage {
+    /// `None` for the first chunk of an upload.
+    pub upload_bytes: Option<u64>,
+    /// Bytes of the earlier copy of a resent chunk.
+    pub replaced: u64,
+    pub chunk_bytes: u64,
+    /// Bytes held for all of the client's unfinished uploads.
+    pub in_flight: u64,
+    /// Unfinished uploads on the connection.
+    pub running: usize,
+}
+
+/// Run every limit on an incoming chunk: the upload's size, the client's
+/// bytes in flight, and for a new upload the concurrent uploads and last the
+/// hourly limit, which counts it. The upload's size with the chunk stored.
+pub fn admit_chunk(client_id: &str, usage: &ChunkUsage) -> Result<u64, QuotaExceeded> {
+    let upload_bytes = usage.upload_bytes.unwrap_or(0) + usage.chunk_bytes - usage.replaced;
+    check_upload_bytes(upload_bytes)?;
+    check_in_flight_bytes(usage.in_flight + usage.chunk_bytes - usage.replaced)?;
+
+    if usage.upload_bytes.is_none() {
+        check_concurrent_uploads(usage.running)?;
+        start_upload(client_id)?;
+    }
+    Ok(upload_bytes)
+}
+
+/// Count a new upload against the hourly limit, unless it is already used up.
+pub fn start_upload(client_id: &str) -> Result<(), QuotaExceeded> {
+    let allowed = get().max_uploads_per_hour;
+    let now = Instant::now();
+
+    let mut starts = UPLOAD_STARTS.lock().unwrap();
+    let client_starts = starts.entry(client_id.to_string()).or_default();
+    while client_starts
+        .front()
+        .is_some_and(|start| now.duration_since(*start) >= RATE_WINDOW)
+    {
+        client_starts.pop_front();
+    }
+
+    if client_starts.len() >= allowed {
+        return Err(QuotaExceeded::new(
+            QuotaLimit::UploadsPerHour,
+            allowed as u64,
+            format!("At most {} uploads per hour", allowed),
+        ));
+    }
+    client_starts.push_back(now);
+    Ok(())
+}
+
+/// Log a client running into a limit and tell the UI, at most once per
+/// interval for each client and limit.
+pub fn report(app: &AppHandle, client: &PairedClient, exceeded: &QuotaExceeded) {
+    log_warn!(
+        LogTarget::Websocket,
+        "{} ({}) hit the {:?} limit: {}",
+        client.name,
+        client.id,
+        exceeded.limit,
+        exceeded.error
+    );
+
+    {
+        let mut notices = LAST_EXCEEDED_NOTICE.lock().unwrap();
+        let key = (client.id.clone(), exceeded.limit);
+        let now = Instant::now();
+        if notices
+            .get(&key)
+            .is_some_and(|last| now.duration_since(*last) < EXCEEDED_NOTICE_INTERVAL)
+        {
+            return;
+        }
+        notices.insert(key, now);
+    }
+
+    let _ = app.emit(
+        "quota-exceeded",
+        QuotaExceededEvent {
+            client_id: client.id.clone(),
+            client_name: client.name.clone(),
+            limit: exceeded.limit,
+            allowed: exceeded.allowed,
+            error: exceeded.error.clone(),
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // nothing here changes UPLOAD_QUOTAS, so every test sees the defaults,
+    // and each uses its own client id for the hourly window
+
+    fn uploads_started(client_id: &str) -> usize {
+        UPLOAD_STARTS
+            .lock()
+            .unwrap()
+            .get(client_id)
+            .map_or(0, |starts| starts.len())
+    }
+
+    #[test]
+    fn a_resent_chunk_replaces_its_bytes() {
+        let quotas = UploadQuotas::default();
+        let usage = ChunkUsage {
+            upload_bytes: Some(100),
+            replaced: 30,
+            chunk_bytes: 40,
+            ..ChunkUsage::default()
+        };
+        assert_eq!(admit_chunk("replaced", &usage).unwrap(), 110);
+
+        // at the limits already, a same-sized copy still fits
+        let usage = ChunkUsage {
+            upload_bytes: Some(quotas.max_upload_bytes),
+            replaced: 10,
+            chunk_bytes: 10,
+            in_flight: quotas.max_in_flight_bytes,
+            running: 0,
+        };
+        assert_eq!(
+            admit_chunk("replaced", &usage).unwrap(),
+            quotas.max_upload_bytes
+        );
+    }
+
+    #[test]
+    fn counts_concurrent_uploads_per_connection() {
+        let allowed = UploadQuotas::default().max_concurrent_uploads;
+        let new_upload = |running| ChunkUsage {
+            chunk_bytes: 1,
+            running,
+            ..ChunkUsage::default()
+        };
+        assert!(admit_chunk("concurrent", &new_upload(allowed - 1)).is_ok());
+        let exceeded = admit_chunk("concurrent", &new_upload(allowed)).unwrap_err();
+        assert_eq!(exceeded.limit, QuotaLimit::ConcurrentUploads);
+
+        // only a new upload adds to them
+        let next_chunk = ChunkUsage {
+            upload_bytes: Some(1),
+            ..new_upload(allowed)
+        };
+        assert!(admit_chunk("concurrent", &next_chunk).is_ok());
+    }
+
+    #[test]
+    fn limits_bytes_in_flight_across_uploads() {
+        let allowed = UploadQuotas::default().max_in_flight_bytes;
+        let usage = |chunk_bytes| ChunkUsage {
+            upload_bytes: Some(0),
+            chunk_bytes,
+            in_flight: allowed - 10,
+            ..ChunkUsage::default()
+        };
+        assert!(admit_chunk("in-flight", &usage(10)).is_ok());
+        let exceeded = admit_chunk("in-flight", &usage(11)).unwrap_err();
+        assert_eq!(exceeded.limit, QuotaLimit::InFlightBytes);
+        assert_eq!(exceeded.allowed, allowed);
+    }
+
+    #[test]
+    fn hourly_limit_slides() {
+        let allowed = UploadQuotas::default().max_uploads_per_hour;
+        for _ in 0..allowed {
+            start_upload("hourly").unwrap();
+        }
+        let exceeded = start_upload("hourly").unwrap_err();
+        assert_eq!(exceeded.limit, QuotaLimit::UploadsPerHour);
+        assert_eq!(uploads_started("hourly"), allowed);
+
+        // starts older than the window no longer count
+        let old = Instant::now().checked_sub(RATE_WINDOW).unwrap();
+        UPLOAD_STARTS
+            .lock()
+            .unwrap()
+            .insert("window".to_string(), vec![old; allowed].into());
+        start_upload("window").unwrap();
+        assert_eq!(uploads_started("window"), 1);
+    }
+
+    #[test]
+    fn checks_run_in_order_and_count_the_upload_last() {
+        let quotas = UploadQuotas::default();
+
+        // over every limit, the upload's own size is reported
+        let usage = ChunkUsage {
+            upload_bytes: None,
+            replaced: 0,
+            chunk_bytes: quotas.max_upload_bytes + 1,
+            in_flight: quotas.max_in_flight_bytes,
+            running: quotas.max_concurrent_uploads,
+        };
+        let exceeded = admit_chunk("order", &usage).unwrap_err();
+        assert_eq!(exceeded.limit, QuotaLimit::UploadBytes);
+
+        let usage = ChunkUsage {
+            chunk_bytes: 1,
+            ..usage
+        };
+        let exceeded = admit_chunk("order", &usage).unwrap_err();
+        assert_eq!(exceeded.limit, QuotaLimit::InFlightBytes);
+
+        let usage = ChunkUsage {
+            in_flight: 0,
+            ..usage
+        };
+        let exceeded = admit_chunk("order", &usage).unwrap_err();
+        assert_eq!(exceeded.limit, QuotaLimit::ConcurrentUploads);
+
+        // none of the refusals used up an hourly slot
+        assert_eq!(uploads_started("order"), 0);
+        let usage = ChunkUsage {
+            running: 0,
+            ..usage
+        };
+        admit_chunk("order", &usage).unwrap();
+        assert_eq!(uploads_started("order"), 1);
+    }
+}
@@ -0,0 +1,324 @@
+//! Plain HTTP access to uploads, downloads and status for scripts and curl,
+//! with the same origin and token checks as the websockets. Described by
+//! `openapi.json`, served at `/api/openapi.json`.
+
+use crate::events::NetworkStatus;
+use crate::logging::LogTarget;
+use crate::pairing::PairedClient;
+use crate::protocol::PROTOCOL_VERSION;
+use crate::quotas::{self, QuotaExceeded, QuotaLimit};
+use crate::services::{self, Service, ServiceState};
+use crate::upload_frames::FileInfo;
+use crate::uploads::{self, UploadState};
+use crate::websockets::{with_client, with_handle};
+use crate::WEBSOCKET_PORT;
+use bytes::{Buf, Bytes};
+use futures::StreamExt;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde_json::json;
+use std::convert::Infallible;
+use tauri::AppHandle;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use warp::http::{header, Response, StatusCode};
+use warp::multipart::FormData;
+use warp::{Filter, Reply};
+
+const OPENAPI: &str = include_str!("openapi.json");
+
+// uploads are spooled to disk, this only bounds the disk they may take
+const MAX_UPLOAD_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
+const DOWNLOAD_READ_SIZE: usize = 64 * 1024;
+
+pub fn routes(
+    handle: AppHandle,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    let openapi = warp::path!("api" / "openapi.json")
+        .and(warp::get())
+        .map(|| {
+            warp::reply::with_header(OPENAPI, header::CONTENT_TYPE, "application/json")
+                .into_response()
+        });
+
+    let create_upload = warp::path!("api" / "uploads")
+        .and(warp::post())
+        .and(with_client(handle.clone(), "api/uploads"))
+        .and(warp::multipart::form().max_length(MAX_UPLOAD_BYTES))
+        .and(with_handle(handle.clone()))
+        .and_then(create_upload);
+
+    let get_upload = warp::path!("api" / "uploads" / String)
+        .and(warp::get())
+        .and(with_client(handle.clone(), "api/uploads"))
+        .map(
+            |id: String, client: PairedClient| match uploads::get(&id, &client.id) {
+                Some(record) => warp::reply::json(&record).into_response(),
+                None => error_reply(StatusCode::NOT_FOUND, format!("No upload {}", id)),
+            },
+        );
+
+    let status = warp::path!("api" / "status")
+        .and(warp::get())
+        .and(with_client(handle.clone(), "api/status"))
+        .and(with_handle(handle.clone()))
+        .and_then(get_status);
+
+    let files = warp::path!("api" / "files")
+        .and(warp::get())
+        .and(with_client(handle.clone(), "api/files"))
+        .map(|client: PairedClient| warp::reply::json(&uploads::files(&client.id)).into_response());
+
+    let download = warp::path!("api" / "files" / String)
+        .and(warp::get())
+        .and(with_client(handle.clone(), "api/files"))
+        .and(with_handle(handle))
+        .and_then(download_file);
+
+    openapi
+        .or(create_upload)
+        .unify()
+        .or(get_upload)
+        .unify()
+        .or(status)
+        .unify()
+        .or(files)
+        .unify()
+        .or(download)
+        .unify()
+}
+
+fn error_reply(status: StatusCode, error: String) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&json!({ "error": error })), status).into_response()
+}
+
+/// Takes the `file` part of a multipart form and answers once it is on disk.
+/// Putting it on the network runs on, to be followed at `/api/uploads/{id}`.
+async fn create_upload(
+    client: PairedClient,
+    form: FormData,
+    handle: AppHandle,
+) -> Result<warp::reply::Response, Infallible> {
+    let mut id_bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut id_bytes);
+    let id = hex::encode(id_bytes);
+
+    match receive_upload(&id, &client, form, handle.clone()).await {
+        Ok(()) => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({
+                "id": id,
+                "status_url": format!("/api/uploads/{}", id),
+            })),
+            StatusCode::ACCEPTED,
+        )
+        .into_response()),
+        Err(UploadRefused::Invalid(e)) => {
+            log_warn!(LogTarget::Websocket, "REST upload failed: {}", e);
+            uploads::fail(&id, &client.id, &e);
+            Ok(error_reply(StatusCode::BAD_REQUEST, e))
+        }
+        Err(UploadRefused::Quota(exceeded)) => {
+            quotas::report(&handle, &client, &exceeded);
+            uploads::fail(&id, &client.id, &exceeded.error);
+            let status = match exceeded.limit {
+                QuotaLimit::UploadBytes => StatusCode::PAYLOAD_TOO_LARGE,
+                _ => StatusCode::TOO_MANY_REQUESTS,
+            };
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({
+                    "error": exceeded.error,
+                    "limit": exceeded.limit,
+                    "allowed": exceeded.allowed,
+                })),
+                status,
+            )
+            .into_response())
+        }
+    }
+}
+
+enum UploadRefused {
+    Invalid(String),
+    Quota(QuotaExceeded),
+}
+
+impl From<String> for UploadRefused {
+    fn from(e: String) -> Self {
+        UploadRefused::Invalid(e)
+    }
+}
+
+async fn receive_upload(
+    id: &str,
+    client: &PairedClient,
+    form: FormData,
+    handle: AppHandle,
+) -> Result<(), UploadRefused> {
+    let mut form = Box::pin(form);
+
+    while let Some(part) = form.next().await {
+        let part = part.map_err(|e| format!("Invalid multipart body: {}", e))?;
+        if part.name() != "file" {
+            continue;
+        }
+
+        let file = FileInfo {
+            filename: part.filename().unwrap_or("upload").to_string(),
+            mime_type: part
+                .content_type()
+                .unwrap_or("application/octet-stream")
+                .to_string(),
+        };
+        // a refused upload gets no record
+        quotas::start_upload(&client.id).map_err(UploadRefused::Quota)?;
+        uploads::begin(id, &client.id, &file, UploadState::Receiving);
+
+        let temp_file = tempfile::NamedTempFile::new()
+            .map_err(|e| format!("Failed to create temp file: {}", e))?;
+        let mut output = tokio::fs::File::create(temp_file.path())
+            .await
+            .map_err(|e| format!("Failed to open temp file: {}", e))?;
+
+        let mut received: u64 = 0;
+        let mut data = Box::pin(part.stream());
+        while let Some(buf) = data.next().await {
+            let buf = buf.map_err(|e| format!("Upload interrupted: {}", e))?;
+            received += buf.remaining() as u64;
+            quotas::check_upload_bytes(received).map_err(UploadRefused::Quota)?;
+            output
+                .write_all(buf.chunk())
+                .await
+                .map_err(|e| format!("Failed to write temp file: {}", e))?;
+        }
+        output
+            .flush()
+            .await
+            .map_err(|e| format!("Failed to write temp file: {}", e))?;
+
+        let id = id.to_string();
+        let client_id = client.id.clone();
+        tauri::async_runtime::spawn(async move {
+            // the outcome lands in the upload record
+            let _ = uploads::store(&handle, &id, &client_id, &file, temp_file.path()).await;
+        });
+        return Ok(());
+    }
+
+    Err(UploadRefused::Invalid("The form has no 'file' part".into()))
+}
+
+async fn get_status(
+    _client: PairedClient,
+    handle: AppHandle,
+) -> Result<warp::reply::Response, Infallible> {
+    let online = services::state_event(Service::Ant, None).state == ServiceState::Ready;
+    let websocket_port = *WEBSOCKET_PORT.lock().unwrap();
+
+    Ok(warp::reply::json(&json!({
+        "app_version": handle.package_info().version.to_string(),
+        "protocol_version": PROTOCOL_VERSION,
+        "websocket_port": websocket_port,
+        "services": services::status().await,
+        "network": NetworkStatus { online },
+    }))
+    .into_response())
+}
+
+/// Fetch a file through ant into a temporary directory and stream it back.
+async fn download_file(
+    xorname: String,
+    client: PairedClient,
+    handle: AppHandle,
+) -> Result<warp::reply::Response, Infallible> {
+    if !crate::is_xorname(&xorname) {
+        return Ok(error_reply(
+            StatusCode::BAD_REQUEST,
+            format!("'{}' is not an xorname", xorname),
+        ));
+    }
+
+    let dir = match tempfile::tempdir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            return Ok(error_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to create temp dir: {}", e),
+            ))
+        }
+    };
+    let dest = dir.path().join(&xorname);
+
+    if let Err(e) = crate::download_path(&xorname, &dest, &handle).await {
+        return Ok(error_reply(StatusCode::BAD_GATEWAY, e));
+    }
+
+    let size = match tokio::fs::metadata(&dest).await {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        Ok(_) => {
+            return Ok(error_reply(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("{} is an archive, not a single file", xorname),
+            ))
+        }
+        Err(e) => {
+            return Ok(error_reply(
+                StatusCode::BAD_GATEWAY,
+                format!("Download produced no file: {}", e),
+            ))
+        }
+    };
+    let file = match tokio::fs::File::open(&dest).await {
+        Ok(file) => file,
+        Err(e) => {
+            return Ok(error_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to open download: {}", e),
+            ))
+        }
+    };
+
+    // the temp dir goes once the body has been sent
+    let body = futures::stream::try_unfold((file, dir), |(mut file, dir)| async move {
+        let mut buf = vec![0u8; DOWNLOAD_READ_SIZE];
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            return Ok::<_, std::io::Error>(None);
+        }
+        buf.truncate(read);
+        Ok(Some((Bytes::from(buf), (file, dir))))
+    });
+
+    // name and type are only known for the caller's own files
+    let known = uploads::file(&xorname, &client.id);
+    let mime_type = known
+        .as_ref()
+        .map(|file| file.mime_type.clone())
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    let filename = known
+        .map(|file| {
+            // the header only takes plain ASCII, and quotes would end the name
+            file.name
+                .chars()
+                .map(|c| match c {
+                    '"' | '\\' => '_',
+                    c if c.is_ascii_graphic() || c == ' ' => c,
+                    _ => '_',
+                })
+                .collect::<String>()
+        })
+        .unwrap_or_else(|| xorname.clone());
+
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, mime_type)
+        .header(header::CONTENT_LENGTH, size)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .body(warp::hyper::Body::wrap_stream(body));
+
+    Ok(match response {
+        Ok(response) => response,
+        Err(e) => error_reply(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    })
+}
@@ -0,0 +1,118 @@
+use crate::logging;
+use crate::services::Service;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::ipc::Channel;
+
+// lines kept in memory per service, the oldest are dropped first
+const MAX_ENTRIES: usize = 2000;
+const DEFAULT_LIMIT: usize = 200;
+
+static SERVICE_LOGS: Lazy<Mutex<ServiceLogs>> = Lazy::new(|| Mutex::new(ServiceLogs::default()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+    Lifecycle,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceLogEntry {
+    /// Increases across all services, usable as a cursor for `since`.
+    pub seq: u64,
+    pub timestamp_ms: u64,
+    pub service: Service,
+    pub stream: LogStream,
+    pub line: String,
+}
+
+struct Subscriber {
+    service: Option<Service>,
+    channel: Channel<ServiceLogEntry>,
+}
+
+#[derive(Default)]
+struct ServiceLogs {
+    next_seq: u64,
+    buffers: HashMap<Service, VecDeque<ServiceLogEntry>>,
+    next_subscriber_id: u64,
+    subscribers: HashMap<u64, Subscriber>,
+}
+
+pub fn record(service: Service, stream: LogStream, line: impl Into<String>) {
+    let line = line.into();
+    let level = match stream {
+        LogStream::Stdout => "STDOUT",
+        LogStream::Stderr => "STDERR",
+        LogStream::Lifecycle => "EVENT",
+    };
+    logging::write(service.into(), level, &line);
+
+    let mut logs = SERVICE_LOGS.lock().unwrap();
+
+    logs.next_seq += 1;
+    let entry = ServiceLogEntry {
+        seq: logs.next_seq,
+        timestamp_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0),
+        service,
+        stream,
+        line,
+    };
+
+    // drop subscribers whose frontend side has gone away
+    logs.subscribers.retain(|_, subscriber| {
+        if subscriber.service.is_some_and(|s| s != service) {
+            return true;
+        }
+        subscriber.channel.send(entry.clone()).is_ok()
+    });
+
+    let buffer = logs.buffers.entry(service).or_default();
+    if buffer.len() == MAX_ENTRIES {
+        buffer.pop_front();
+    }
+    buffer.push_back(entry);
+}
+
+/// Entries for `service` with a sequence number above `since`, newest last.
+/// When more than `limit` match, only the most recent ones are returned.
+pub fn query(service: Service, since: Option<u64>, limit: Option<usize>) -> Vec<ServiceLogEntry> {
+    let logs = SERVICE_LOGS.lock().unwrap();
+    let Some(buffer) = logs.buffers.get(&service) else {
+        return Vec::new();
+    };
+
+    let since = since.unwrap_or(0);
+    let limit = limit.unwrap_or(DEFAULT_LIMIT);
+
+    let matching: Vec<&ServiceLogEntry> = buffer.iter().filter(|entry| entry.seq > since).collect();
+    let skip = matching.len().saturating_sub(limit);
+
+    matching.into_iter().skip(skip).cloned().collect()
+}
+
+/// Stream new entries to `channel`. A `None` service subscribes to all of them.
+pub fn subscribe(service: Option<Service>, channel: Channel<ServiceLogEntry>) -> u64 {
+    let mut logs = SERVICE_LOGS.lock().unwrap();
+    logs.next_subscriber_id += 1;
+    let id = logs.next_subscriber_id;
+    logs.subscribers.insert(id, Subscriber { service, channel });
+    id
+}
+
+pub fn unsubscribe(id: u64) -> bool {
+    SERVICE_LOGS
+        .lock()
+        .unwrap()
+        .subscribers
+        .remove(&id)
+        .is_some()
+}
@@ -0,0 +1,885 @@
+use crate::events::{self, NetworkStatus, PushEvent};
+use crate::integrity;
+use crate::orphans;
+use crate::service_logs::{self, LogStream};
+use crate::settings;
+use crate::sidecar_config;
+use crate::types::{ServiceStateEvent, ServiceStatus, ShutdownReport};
+use crate::updates;
+use crate::{ANTTP_PORT, ANT_PORT, DWEB_PORT};
+use once_cell::sync::Lazy;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::async_runtime::Receiver;
+use tauri::{AppHandle, Emitter};
+use tauri_plugin_shell::process::{Command, CommandChild, CommandEvent};
+use tauri_plugin_shell::ShellExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+
+// restart policy for crashed sidecars
+const MAX_RESTARTS: u32 = 5;
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// readiness probing after a spawn
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+const PROBE_INTERVAL: Duration = Duration::from_millis(500);
+const READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+// graceful shutdown before escalating to a kill
+const SHUTDOWN_GRACE_KEY: &str = "shutdown-grace-ms";
+const DEFAULT_SHUTDOWN_GRACE_MS: u64 = 5_000;
+const MAX_SHUTDOWN_GRACE_MS: u64 = 60_000;
+const KILL_WAIT: Duration = Duration::from_secs(2);
+
+static SHUTDOWN_GRACE_MS: Lazy<Mutex<u64>> = Lazy::new(|| Mutex::new(DEFAULT_SHUTDOWN_GRACE_MS));
+
+// a sidecar that stayed up at least this long gets its restart budget back
+const STABLE_UPTIME: Duration = Duration::from_secs(60);
+
+static ANT_PROCESS: Lazy<Mutex<ServiceProcess>> =
+    Lazy::new(|| Mutex::new(ServiceProcess::default()));
+static ANTTP_PROCESS: Lazy<Mutex<ServiceProcess>> =
+    Lazy::new(|| Mutex::new(ServiceProcess::default()));
+static DWEB_PROCESS: Lazy<Mutex<ServiceProcess>> =
+    Lazy::new(|| Mutex::new(ServiceProcess::default()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Service {
+    Ant,
+    Anttp,
+    Dweb,
+}
+
+impl Service {
+    pub const ALL: [Service; 3] = [Service::Ant, Service::Anttp, Service::Dweb];
+
+    /// Sidecar binary name, also used as the prefix of its stdout event.
+    pub fn name(self) -> &'static str {
+        match self {
+            Service::Ant => "ant",
+            Service::Anttp => "anttp",
+            Service::Dweb => "dweb",
+        }
+    }
+
+    pub fn port(self) -> u16 {
+        match self {
+            Service::Ant => *ANT_PORT.lock().unwrap(),
+            Service::Anttp => *ANTTP_PORT.lock().unwrap(),
+            Service::Dweb => *DWEB_PORT.lock().unwrap(),
+        }
+    }
+
+    /// Sidecars always listen on loopback whatever the bind setting, they
+    /// can't check the network allowlist themselves.
+    fn args(self, port: u16) -> Vec<String> {
+        match self {
+            Service::Ant | Service::Anttp => {
+                vec!["-l".to_string(), sidecar_addr(port).to_string()]
+            }
+            Service::Dweb => vec!["serve".to_string(), "--port".to_string(), port.to_string()],
+        }
+    }
+
+    pub fn set_port(self, port: u16) {
+        match self {
+            Service::Ant => *ANT_PORT.lock().unwrap() = port,
+            Service::Anttp => *ANTTP_PORT.lock().unwrap() = port,
+            Service::Dweb => *DWEB_PORT.lock().unwrap() = port,
+        }
+    }
+
+    /// Where the bundled sidecar lives, mirroring how the shell plugin resolves it.
+    pub fn binary_path(self) -> Result<PathBuf, String> {
+        let exe = tauri::utils::platform::current_exe()
+            .map_err(|e| format!("Failed to locate current exe: {}", e))?;
+        let dir = exe.parent().ok_or("Current exe has no parent directory")?;
+
+        let mut path = dir.join(self.name());
+        if cfg!(windows) {
+            path.set_extension("exe");
+        }
+        Ok(path)
+    }
+
+    fn process(self) -> &'static Mutex<ServiceProcess> {
+        match self {
+            Service::Ant => &ANT_PROCESS,
+            Service::Anttp => &ANTTP_PROCESS,
+            Service::Dweb => &DWEB_PROCESS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, JsonSchema)]
+pub enum ServiceState {
+    #[default]
+    Stopped,
+    Starting,
+    Ready,
+    Crashed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum ShutdownOutcome {
+    NotRunning,
+    /// Exited on its own within the grace period.
+    Terminated,
+    /// Had to be force-killed.
+    Killed,
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+struct ExitInfo {
+    code: Option<i32>,
+    signal: Option<i32>,
+}
+
+#[derive(Default)]
+struct ServiceProcess {
+    child: Option<CommandChild>,
+    // filled by the supervisor once the process has exited
+    exit_rx: Option<watch::Receiver<Option<ExitInfo>>>,
+    // a sidecar left over from a previous session that we took over, we
+    // only have its pid and watch it by polling
+    adopted_pid: Option<u32>,
+    state: ServiceState,
+    // bumped on every spawn and every intentional stop, so a watcher can tell
+    // whether the exit it observed still belongs to the current process
+    generation: u64,
+    started_at: Option<Instant>,
+    restart_count: u32,
+    last_exit_code: Option<i32>,
+}
+
+impl ServiceProcess {
+    fn is_running(&self) -> bool {
+        self.child.is_some() || self.adopted_pid.is_some()
+    }
+
+    fn pid(&self) -> Option<u32> {
+        self.child
+            .as_ref()
+            .map(|child| child.pid())
+            .or(self.adopted_pid)
+    }
+}
+
+pub fn is_running(service: Service) -> bool {
+    service.process().lock().unwrap().is_running()
+}
+
+pub fn pid(service: Service) -> Option<u32> {
+    service.process().lock().unwrap().pid()
+}
+
+/// Start a sidecar on user request, resetting its restart budget.
+pub fn start(app: &AppHandle, service: Service) -> Result<(), String> {
+    service.process().lock().unwrap().restart_count = 0;
+    spawn_service(app, service)
+}
+
+/// Start several sidecars as one unit. If any of them fails to spawn, the ones
+/// already started by this call are stopped again before the error is returned.
+pub async fn start_batch(app: &AppHandle, services: &[Service]) -> Result<(), String> {
+    let mut started = Vec::new();
+
+    for service in services {
+        if let Err(e) = start(app, *service) {
+            for started_service in started.into_iter().rev() {
+                stop(started_service).await;
+                emit_state(
+                    app,
+                    started_service,
+                    Some("Rolled back after failed start".into()),
+                );
+            }
+            return Err(e);
+        }
+        started.push(*service);
+    }
+
+    Ok(())
+}
+
+/// Stop a sidecar on user request. The supervisor will not restart it.
+///
+/// The process is asked to terminate first and only killed once the grace
+/// period runs out, so ant can flush its state and finish a pending payment.
+pub async fn stop(service: Service) -> ShutdownReport {
+    let grace = shutdown_grace_period();
+    let (child, exit_rx, adopted_pid) = {
+        let mut process = service.process().lock().unwrap();
+        process.generation += 1;
+        process.state = ServiceState::Stopped;
+        process.started_at = None;
+        (
+            process.child.take(),
+            process.exit_rx.take(),
+            process.adopted_pid.take(),
+        )
+    };
+    orphans::forget(service);
+
+    let report = |outcome: ShutdownOutcome, exit: Option<ExitInfo>| ShutdownReport {
+        service,
+        outcome,
+        exit_code: exit.as_ref().and_then(|exit| exit.code),
+        signal: exit.as_ref().and_then(|exit| exit.signal),
+    };
+
+    if let Some(pid) = adopted_pid {
+        return report(terminate_pid(pid, grace).await, None);
+    }
+
+    let (Some(child), Some(mut exit_rx)) = (child, exit_rx) else {
+        return report(ShutdownOutcome::NotRunning, None);
+    };
+
+    let pid = child.pid();
+    service_logs::record(
+        service,
+        LogStream::Lifecycle,
+        format!(
+            "Stopping pid {} ({}ms grace period)",
+            pid,
+            grace.as_millis()
+        ),
+    );
+
+    let shutdown = match terminate(pid) {
+        Ok(()) => match wait_for_exit(&mut exit_rx, grace).await {
+            Some(exit) => report(ShutdownOutcome::Terminated, Some(exit)),
+            None => force_kill(child, &mut exit_rx, &report).await,
+        },
+        Err(e) => {
+            service_logs::record(service, LogStream::Lifecycle, e);
+            force_kill(child, &mut exit_rx, &report).await
+        }
+    };
+
+    service_logs::record(
+        service,
+        LogStream::Lifecycle,
+        format!(
+            "Stopped: {:?} (exit code {:?}, signal {:?})",
+            shutdown.outcome, shutdown.exit_code, shutdown.signal
+        ),
+    );
+
+    shutdown
+}
+
+pub async fn stop_all() -> Vec<ShutdownReport> {
+    futures::future::join_all(Service::ALL.map(stop)).await
+}
+
+async fn force_kill(
+    child: CommandChild,
+    exit_rx: &mut watch::Receiver<Option<ExitInfo>>,
+    report: &impl Fn(ShutdownOutcome, Option<ExitInfo>) -> ShutdownReport,
+) -> ShutdownReport {
+    if let Err(e) = child.kill() {
+        return report(ShutdownOutcome::Failed(e.to_string()), None);
+    }
+    let exit = wait_for_exit(exit_rx, KILL_WAIT).await;
+    report(ShutdownOutcome::Killed, exit)
+}
+
+async fn wait_for_exit(
+    exit_rx: &mut watch::Receiver<Option<ExitInfo>>,
+    timeout: Duration,
+) -> Option<ExitInfo> {
+    match tokio::time::timeout(timeout, exit_rx.wait_for(|exit| exit.is_some())).await {
+        Ok(Ok(exit)) => exit.clone(),
+        _ => None,
+    }
+}
+
+/// Graceful shutdown for a process we have no child handle for, like an
+/// adopted or orphaned sidecar. Exit codes aren't observable this way.
+pub async fn terminate_pid(pid: u32, grace: Duration) -> ShutdownOutcome {
+    if !process_alive(pid) {
+        return ShutdownOutcome::NotRunning;
+    }
+
+    if terminate(pid).is_ok() && wait_until_dead(pid, grace).await {
+        return ShutdownOutcome::Terminated;
+    }
+
+    if let Err(e) = force_kill_pid(pid) {
+        return ShutdownOutcome::Failed(e);
+    }
+
+    if wait_until_dead(pid, KILL_WAIT).await {
+        ShutdownOutcome::Killed
+    } else {
+        ShutdownOutcome::Failed(format!("pid {} survived SIGKILL", pid))
+    }
+}
+
+async fn wait_until_dead(pid: u32, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if !process_alive(pid) {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    !process_alive(pid)
+}
+
+#[cfg(unix)]
+fn send_signal(pid: u32, signal: libc::c_int) -> Result<(), std::io::Error> {
+    // SAFETY: plain kill(2), callers only pass pids of sidecars they verified
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(unix)]
+fn terminate(pid: u32) -> Result<(), String> {
+    send_signal(pid, libc::SIGTERM).map_err(|e| format!("SIGTERM to pid {} failed: {}", pid, e))
+}
+
+#[cfg(unix)]
+fn force_kill_pid(pid: u32) -> Result<(), String> {
+    send_signal(pid, libc::SIGKILL).map_err(|e| format!("SIGKILL to pid {} failed: {}", pid, e))
+}
+
+#[cfg(unix)]
+pub fn process_alive(pid: u32) -> bool {
+    // signal 0 only checks; EPERM still means the process exists
+    match send_signal(pid, 0) {
+        Ok(()) => true,
+        Err(e) => e.raw_os_error() == Some(libc::EPERM),
+    }
+}
+
+#[cfg(not(unix))]
+fn terminate(pid: u32) -> Result<(), String> {
+    // console sidecars have no window to receive WM_CLOSE, so there is no polite way in
+    Err(format!(
+        "No graceful terminate for pid {} on this platform",
+        pid
+    ))
+}
+
+#[cfg(not(unix))]
+fn force_kill_pid(pid: u32) -> Result<(), String> {
+    Err(format!(
+        "Can't kill pid {} without a child handle on this platform",
+        pid
+    ))
+}
+
+#[cfg(not(unix))]
+pub fn process_alive(_pid: u32) -> bool {
+    false
+}
+
+pub fn shutdown_grace_period() -> Duration {
+    Duration::from_millis(*SHUTDOWN_GRACE_MS.lock().unwrap())
+}
+
+/// Load the persisted grace period. Called once during setup.
+pub fn init(app: &AppHandle) {
+    if let Some(grace_ms) = settings::load::<u64>(app, SHUTDOWN_GRACE_KEY) {
+        if validate_grace_period(grace_ms).is_ok() {
+            *SHUTDOWN_GRACE_MS.lock().unwrap() = grace_ms;
+        }
+    }
+}
+
+pub fn set_shutdown_grace_period(app: &AppHandle, grace_ms: u64) -> Result<(), String> {
+    validate_grace_period(grace_ms)?;
+    settings::save(app, SHUTDOWN_GRACE_KEY, &grace_ms)?;
+    *SHUTDOWN_GRACE_MS.lock().unwrap() = grace_ms;
+    Ok(())
+}
+
+fn validate_grace_period(grace_ms: u64) -> Result<(), String> {
+    if grace_ms > MAX_SHUTDOWN_GRACE_MS {
+        return Err(format!(
+            "Grace period must be at most {}ms",
+            MAX_SHUTDOWN_GRACE_MS
+        ));
+    }
+    Ok(())
+}
+
+/// Snapshot of every sidecar. Running services are probed on the spot, so a
+/// process that is alive but not answering is reported as Starting.
+pub async fn status() -> Vec<ServiceStatus> {
+    let mut statuses = Vec::new();
+
+    for service in Service::ALL {
+        let port = service.port();
+        let (generation, running) = {
+            let process = service.process().lock().unwrap();
+            (process.generation, process.is_running())
+        };
+
+        let ready = running && probe(service, port).await;
+
+        let mut process = service.process().lock().unwrap();
+
+        // don't overwrite the state of a process that was replaced while probing
+        if running && process.generation == generation {
+            process.state = if ready {
+                ServiceState::Ready
+            } else {
+                ServiceState::Starting
+            };
+        }
+
+        statuses.push(ServiceStatus {
+            service,
+            state: process.state,
+            pid: process.pid(),
+            port,
+            uptime_secs: process
+                .started_at
+                .map(|started| started.elapsed().as_secs()),
+            restart_count: process.restart_count,
+            last_exit_code: process.last_exit_code,
+        });
+    }
+
+    statuses
+}
+
+/// Where a sidecar stands, as sent to the UI and to websocket subscribers.
+pub fn state_event(service: Service, message: Option<String>) -> ServiceStateEvent {
+    let process = service.process().lock().unwrap();
+    ServiceStateEvent {
+        service: service.name().to_string(),
+        state: process.state,
+        exit_code: process.last_exit_code,
+        restart_count: process.restart_count,
+        message,
+    }
+}
+
+pub fn emit_state(app: &AppHandle, service: Service, message: Option<String>) {
+    let event = state_event(service, message);
+
+    let line = match &event.message {
+        Some(message) => format!("{:?}: {}", event.state, message),
+        None => format!("{:?}", event.state),
+    };
+    service_logs::record(service, LogStream::Lifecycle, line);
+
+    if service == Service::Ant {
+        events::publish(PushEvent::NetworkStatus(NetworkStatus {
+            online: event.state == ServiceState::Ready,
+        }));
+    }
+    events::publish(PushEvent::ServiceStatus(event.clone()));
+    let _ = app.emit("service-state", event);
+}
+
+/// Command for the selected version of a sidecar, the bundled one unless an
+/// update was installed and selected. Refused when that binary fails the
+/// integrity check. Every invocation of ant, anttp or dweb goes through here.
+pub fn sidecar_command(app: &AppHandle, service: Service) -> Result<Command, String> {
+    let active = updates::active_binary(service)?;
+    let report = integrity::check(service, &active.path, active.sha256.as_deref());
+    if !report.ok {
+        let error = format!(
+            "Refusing to run {}: {}",
+            service.name(),
+            report.error.clone().unwrap_or_default()
+        );
+        service_logs::record(service, LogStream::Lifecycle, error.clone());
+        let _ = app.emit("sidecar-integrity", report);
+        return Err(error);
+    }
+
+    match active.version {
+        Some(_) => Ok(app.shell().command(active.path)),
+        None => app
+            .shell()
+            .sidecar(service.name())
+            .map_err(|e| format!("Failed to create {} sidecar: {}", service.name(), e)),
+    }
+}
+
+fn spawn_service(app: &AppHandle, service: Service) -> Result<(), String> {
+    if service.process().lock().unwrap().is_running() {
+        return Err(format!("{} is already running", service.name()));
+    }
+
+    // re-validated here, the working directory may have gone away since it was saved
+    let config = sidecar_config::get(service);
+    sidecar_config::validate(service, &config)?;
+
+    // hashing the binary takes a while, so the command is built and verified
+    // before the process lock is taken
+    let mut cmd = sidecar_command(app, service)?;
+    let binary = updates::active_binary(service).map(|active| active.path);
+
+    let mut args = service.args(service.port());
+    args.extend(config.extra_args);
+
+    cmd = cmd.args(&args).envs(config.env);
+    if let Some(dir) = config.working_dir {
+        cmd = cmd.current_dir(dir);
+    }
+
+    let mut process = service.process().lock().unwrap();
+    // it may have been started while the binary was being checked
+    if process.is_running() {
+        return Err(format!("{} is already running", service.name()));
+    }
+
+    let (rx, child) = match cmd.spawn() {
+        Ok(spawned) => spawned,
+        Err(e) => {
+            drop(process);
+            let error = format!("Failed to spawn {}: {}", service.name(), e);
+            service_logs::record(service, LogStream::Lifecycle, error.clone());
+            return Err(error);
+        }
+    };
+    let pid = child.pid();
+
+    let (exit_tx, exit_rx) = watch::channel(None);
+
+    process.generation += 1;
+    process.child = Some(child);
+    process.exit_rx = Some(exit_rx);
+    process.state = ServiceState::Starting;
+    process.started_at = Some(Instant::now());
+
+    let generation = process.generation;
+    drop(process);
+
+    service_logs::record(
+        service,
+        LogStream::Lifecycle,
+        format!("Spawned pid {} with args {:?}", pid, args),
+    );
+    if let Ok(binary) = binary {
+        orphans::remember(service, pid, service.port(), binary);
+    }
+
+    emit_state(app, service, None);
+
+    tauri::async_runtime::spawn(supervise(app.clone(), service, generation, rx, exit_tx));
+    tauri::async_runtime::spawn(wait_until_ready(app.clone(), service, generation));
+
+    Ok(())
+}
+
+async fn supervise(
+    app: AppHandle,
+    service: Service,
+    generation: u64,
+    mut rx: Receiver<CommandEvent>,
+    exit_tx: watch::Sender<Option<ExitInfo>>,
+) {
+    let message_event = format!("{}-message", service.name());
+    let mut exit = ExitInfo {
+        code: None,
+        signal: None,
+    };
+
+    while let Some(event) = rx.recv().await {
+        match event {
+            CommandEvent::Stdout(bytes) => {
+                let line = String::from_utf8_lossy(&bytes).to_string();
+                service_logs::record(service, LogStream::Stdout, line.clone());
+                let _ = app.emit(&message_event, Some(line));
+            }
+            CommandEvent::Stderr(bytes) => {
+                let line = String::from_utf8_lossy(&bytes);
+                service_logs::record(service, LogStream::Stderr, line);
+            }
+            CommandEvent::Error(e) => {
+                service_logs::record(service, LogStream::Lifecycle, format!("Error: {}", e));
+            }
+            CommandEvent::Terminated(payload) => {
+                service_logs::record(
+                    service,
+                    LogStream::Lifecycle,
+                    format!(
+                        "Terminated (code {:?}, signal {:?})",
+                        payload.code, payload.signal
+                    ),
+                );
+                exit = ExitInfo {
+                    code: payload.code,
+                    signal: payload.signal,
+                };
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    // the channel closing without a Terminated event is treated as an exit too
+    let exit_code = exit.code;
+    let _ = exit_tx.send(Some(exit));
+    handle_exit(app, service, generation, exit_code).await;
+}
+
+/// Take over a sidecar left running by a previous session. It is supervised
+/// like one we spawned, except that its exit is noticed by polling.
+pub fn adopt(app: &AppHandle, service: Service, pid: u32) -> Result<(), String> {
+    let generation = {
+        let mut process = service.process().lock().unwrap();
+        if process.is_running() {
+            return Err(format!("{} is already running", service.name()));
+        }
+
+        process.generation += 1;
+        process.adopted_pid = Some(pid);
+        process.state = ServiceState::Starting;
+        process.started_at = Some(Instant::now());
+        process.restart_count = 0;
+        process.generation
+    };
+
+    service_logs::record(
+        service,
+        LogStream::Lifecycle,
+        format!("Adopted pid {} from a previous session", pid),
+    );
+    emit_state(app, service, None);
+
+    tauri::async_runtime::spawn(watch_adopted(app.clone(), service, generation, pid));
+    tauri::async_runtime::spawn(wait_until_ready(app.clone(), service, generation));
+
+    Ok(())
+}
+
+async fn watch_adopted(app: AppHandle, service: Service, generation: u64, pid: u32) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        if service.process().lock().unwrap().generation != generation {
+            return;
+        }
+        if !process_alive(pid) {
+            break;
+        }
+    }
+
+    service_logs::record(
+        service,
+        LogStream::Lifecycle,
+        format!("Adopted pid {} exited", pid),
+    );
+    handle_exit(app, service, generation, None).await;
+}
+
+/// Wait for a sidecar that was just started to answer on its port. Fails
+/// once the process exits, even if the supervisor restarts it.
+pub async fn wait_ready(service: Service) -> Result<(), String> {
+    let generation = service.process().lock().unwrap().generation;
+    let deadline = Instant::now() + READY_TIMEOUT;
+
+    loop {
+        {
+            let process = service.process().lock().unwrap();
+            if process.generation != generation || !process.is_running() {
+                return Err(format!("{} exited before it was ready", service.name()));
+            }
+            if process.state == ServiceState::Ready {
+                return Ok(());
+            }
+        }
+
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "{} is not answering on port {} after {}s",
+                service.name(),
+                service.port(),
+                READY_TIMEOUT.as_secs()
+            ));
+        }
+        tokio::time::sleep(PROBE_INTERVAL).await;
+    }
+}
+
+async fn wait_until_ready(app: AppHandle, service: Service, generation: u64) {
+    let port = service.port();
+    let deadline = Instant::now() + READY_TIMEOUT;
+
+    while Instant::now() < deadline {
+        tokio::time::sleep(PROBE_INTERVAL).await;
+
+        if service.process().lock().unwrap().generation != generation {
+            return;
+        }
+
+        if probe(service, port).await {
+            {
+                let mut process = service.process().lock().unwrap();
+                if process.generation != generation {
+                    return;
+                }
+                process.state = ServiceState::Ready;
+            }
+            emit_state(&app, service, None);
+            return;
+        }
+    }
+
+    emit_state(
+        &app,
+        service,
+        Some(format!(
+            "{} is not answering on port {} after {}s",
+            service.name(),
+            port,
+            READY_TIMEOUT.as_secs()
+        )),
+    );
+}
+
+/// anttp and dweb must answer HTTP; for ant an accepted connection is enough.
+async fn probe(service: Service, port: u16) -> bool {
+    match service {
+        Service::Ant => connect(port).await.is_some(),
+        Service::Anttp | Service::Dweb => probe_http(port).await,
+    }
+}
+
+fn sidecar_addr(port: u16) -> SocketAddr {
+    SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)
+}
+
+async fn connect(port: u16) -> Option<TcpStream> {
+    match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(sidecar_addr(port))).await {
+        Ok(Ok(stream)) => Some(stream),
+        _ => None,
+    }
+}
+
+async fn probe_http(port: u16) -> bool {
+    let Some(mut stream) = connect(port).await else {
+        return false;
+    };
+
+    let request = format!(
+        "HEAD / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        sidecar_addr(port)
+    );
+    if stream.write_all(request.as_bytes()).await.is_err() {
+        return false;
+    }
+
+    // any status line counts, a 404 still means the server is up
+    let mut status_line = [0u8; 5];
+    matches!(
+        tokio::time::timeout(PROBE_TIMEOUT, stream.read_exact(&mut status_line)).await,
+        Ok(Ok(_))
+    ) && &status_line == b"HTTP/"
+}
+
+async fn handle_exit(app: AppHandle, service: Service, generation: u64, exit_code: Option<i32>) {
+    {
+        let mut process = service.process().lock().unwrap();
+
+        // stopped on purpose, or already replaced by a newer process
+        if process.generation != generation {
+            return;
+        }
+
+        if process
+            .started_at
+            .is_some_and(|started| started.elapsed() >= STABLE_UPTIME)
+        {
+            process.restart_count = 0;
+        }
+
+        process.child = None;
+        process.exit_rx = None;
+        process.adopted_pid = None;
+        process.state = ServiceState::Crashed;
+        process.started_at = None;
+        process.last_exit_code = exit_code;
+    }
+    orphans::forget(service);
+
+    emit_state(
+        &app,
+        service,
+        Some(format!(
+            "{} exited unexpectedly (exit code {:?})",
+            service.name(),
+            exit_code
+        )),
+    );
+
+    loop {
+        let attempt = {
+            let mut process = service.process().lock().unwrap();
+            if process.generation != generation {
+                return;
+            }
+            if process.restart_count >= MAX_RESTARTS {
+                None
+            } else {
+                process.restart_count += 1;
+                Some(process.restart_count)
+            }
+        };
+
+        let Some(attempt) = attempt else {
+            emit_state(
+                &app,
+                service,
+                Some(format!(
+                    "{} crashed {} times, giving up",
+                    service.name(),
+                    MAX_RESTARTS
+                )),
+            );
+            return;
+        };
+
+        let delay = backoff(attempt);
+        emit_state(
+            &app,
+            service,
+            Some(format!(
+                "Restarting {} in {}s (attempt {}/{})",
+                service.name(),
+                delay.as_secs(),
+                attempt,
+                MAX_RESTARTS
+            )),
+        );
+
+        tokio::time::sleep(delay).await;
+
+        // the user may have started or stopped the service while we waited
+        if service.process().lock().unwrap().generation != generation {
+            return;
+        }
+
+        match spawn_service(&app, service) {
+            Ok(()) => return,
+            Err(e) => emit_state(&app, service, Some(e)),
+        }
+    }
+}
+
+fn backoff(attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    BASE_BACKOFF.saturating_mul(factor).min(MAX_BACKOFF)
+}
@@ -0,0 +1,40 @@
+use crate::get_app_store_path;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tauri::AppHandle;
+use tauri_plugin_store::StoreBuilder;
+
+// backend-owned settings live in the same store.bin as the frontend's, under their own keys
+
+pub fn load<T: DeserializeOwned>(app: &AppHandle, key: &str) -> Option<T> {
+    let store = StoreBuilder::new(app, get_app_store_path().ok()?)
+        .build()
+        .ok()?;
+    let value = store.get(key)?;
+
+    match serde_json::from_value(value) {
+        Ok(parsed) => Some(parsed),
+        Err(e) => {
+            log_warn!(
+                crate::logging::LogTarget::Backend,
+                "Ignoring invalid '{}' setting: {}",
+                key,
+                e
+            );
+            None
+        }
+    }
+}
+
+pub fn save<T: Serialize>(app: &AppHandle, key: &str, value: &T) -> Result<(), String> {
+    let store = StoreBuilder::new(app, get_app_store_path()?)
+        .build()
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    let value =
+        serde_json::to_value(value).map_err(|e| format!("Failed to serialize {}: {}", key, e))?;
+    store.set(key, value);
+    store
+        .save()
+        .map_err(|e| format!("Failed to save store: {}", e))
+}
@@ -0,0 +1,201 @@
+use crate::services::Service;
+use crate::settings;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Mutex;
+use tauri::AppHandle;
+
+const SIDECAR_CONFIG_KEY: &str = "sidecar-config";
+
+static SIDECAR_CONFIGS: Lazy<Mutex<HashMap<Service, SidecarConfig>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// User supplied additions to a sidecar's command line. The listen address and
+/// port flags stay under our control and can't be overridden here.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SidecarConfig {
+    pub extra_args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    pub working_dir: Option<String>,
+}
+
+fn managed_flags(service: Service) -> &'static [&'static str] {
+    match service {
+        Service::Ant | Service::Anttp => &["-l", "--listen", "--listen-address"],
+        Service::Dweb => &["-p", "--port"],
+    }
+}
+
+fn is_managed_flag(service: Service, arg: &str) -> bool {
+    managed_flags(service).iter().any(|flag| {
+        let Some(rest) = arg.strip_prefix(flag) else {
+            return false;
+        };
+        // "--port", "--port=1234" and the attached short form "-l127.0.0.1:1",
+        // but not other flags that only share the prefix, like "-log"
+        rest.is_empty()
+            || rest.starts_with('=')
+            || (!flag.starts_with("--")
+                && rest.starts_with(|c: char| c.is_ascii_digit() || c == '[' || c == ':'))
+    })
+}
+
+pub fn validate(service: Service, config: &SidecarConfig) -> Result<(), String> {
+    for arg in &config.extra_args {
+        if arg.contains('\0') {
+            return Err(format!("Argument {:?} contains a NUL byte", arg));
+        }
+        if is_managed_flag(service, arg) {
+            return Err(format!(
+                "Argument '{}' is managed by SafeBox and can't be set for {}",
+                arg,
+                service.name()
+            ));
+        }
+    }
+
+    for (key, value) in &config.env {
+        if key.is_empty() || key.contains('=') || key.contains('\0') {
+            return Err(format!("Invalid environment variable name {:?}", key));
+        }
+        if value.contains('\0') {
+            return Err(format!("Environment variable {} contains a NUL byte", key));
+        }
+    }
+
+    if let Some(dir) = &config.working_dir {
+        if !Path::new(dir).is_dir() {
+            return Err(format!("Working directory '{}' does not exist", dir));
+        }
+    }
+
+    Ok(())
+}
+
+/// Load the persisted sidecar configs. Called once during setup.
+pub fn init(app: &AppHandle) {
+    let Some(saved) = settings::load::<HashMap<Service, SidecarConfig>>(app, SIDECAR_CONFIG_KEY)
+    else {
+        return;
+    };
+
+    let mut configs = SIDECAR_CONFIGS.lock().unwrap();
+    for (service, config) in saved {
+        // keep invalid entries out, they would only fail at spawn time
+        match validate(service, &config) {
+            Ok(()) => {
+                configs.insert(service, config);
+            }
+            Err(e) => log_warn!(
+                crate::logging::LogTarget::Backend,
+                "Ignoring saved {} config: {}",
+                service.name(),
+                e
+            ),
+        }
+    }
+}
+
+pub fn get(service: Service) -> SidecarConfig {
+    SIDECAR_CONFIGS
+        .lock()
+        .unwrap()
+        .get(&service)
+        .cloned()
+        .unwrap_or_default()
+}
+
+pub fn set(app: &AppHandle, service: Service, config: SidecarConfig) -> Result<(), String> {
+    validate(service, &config)?;
+
+    let configs = {
+        let mut configs = SIDECAR_CONFIGS.lock().unwrap();
+        configs.insert(service, config);
+        configs.clone()
+    };
+
+    settings::save(app, SIDECAR_CONFIG_KEY, &configs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> SidecarConfig {
+        SidecarConfig {
+            extra_args: args.iter().map(|arg| arg.to_string()).collect(),
+            ..SidecarConfig::default()
+        }
+    }
+
+    #[test]
+    fn refuses_managed_flags() {
+        for arg in [
+            "-l",
+            "--listen",
+            "--listen=0.0.0.0:1",
+            "-l127.0.0.1:1",
+            "-l[::1]:1",
+            "-l:1",
+        ] {
+            assert!(validate(Service::Ant, &args(&[arg])).is_err(), "{}", arg);
+            assert!(validate(Service::Anttp, &args(&[arg])).is_err(), "{}", arg);
+        }
+        for arg in ["-p", "--port", "--port=5537", "-p5537"] {
+            assert!(validate(Service::Dweb, &args(&[arg])).is_err(), "{}", arg);
+        }
+    }
+
+    #[test]
+    fn allows_other_flags_sharing_a_prefix() {
+        for arg in [
+            "-log",
+            "--listener-count",
+            "--listen-addresses-file",
+            "--log-output-dest",
+        ] {
+            assert!(validate(Service::Ant, &args(&[arg])).is_ok(), "{}", arg);
+        }
+        for arg in ["-print", "--portable", "--hostname", "-p-x"] {
+            assert!(validate(Service::Dweb, &args(&[arg])).is_ok(), "{}", arg);
+        }
+        // every argument is checked on its own, wherever it appears
+        assert!(validate(Service::Ant, &args(&["--peer", "-l1"])).is_err());
+        assert!(validate(Service::Ant, &args(&["--peer", "/ip4/1.2.3.4"])).is_ok());
+    }
+
+    #[test]
+    fn refuses_nul_bytes_and_bad_env_names() {
+        assert!(validate(Service::Ant, &args(&["--peer\0"])).is_err());
+
+        for (key, value) in [("", "1"), ("A=B", "1"), ("A\0", "1"), ("A", "1\0")] {
+            let config = SidecarConfig {
+                env: BTreeMap::from([(key.to_string(), value.to_string())]),
+                ..SidecarConfig::default()
+            };
+            assert!(validate(Service::Ant, &config).is_err(), "{:?}", key);
+        }
+
+        let config = SidecarConfig {
+            env: BTreeMap::from([("RUST_LOG".to_string(), "debug".to_string())]),
+            ..SidecarConfig::default()
+        };
+        assert!(validate(Service::Ant, &config).is_ok());
+    }
+
+    #[test]
+    fn working_dir_must_exist() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = SidecarConfig {
+            working_dir: Some(dir.path().to_string_lossy().to_string()),
+            ..SidecarConfig::default()
+        };
+        assert!(validate(Service::Dweb, &config).is_ok());
+
+        config.working_dir = Some(dir.path().join("missing").to_string_lossy().to_string());
+        assert!(validate(Service::Dweb, &config).is_err());
+    }
+}
@@ -1,11 +1,4 @@
-#[derive(serde::Serialize, serde::Deserialize, Clone)]
-pub struct UploadFilePayload {
-    pub name: String,
-    pub mime_type: String,
-    pub data: Vec<u8>, //file bytes
-}
-
-#[derive(serde::Serialize, Clone)]
+#[derive(Debug, serde::Serialize, Clone, schemars::JsonSchema)]
 pub struct UploadFileEvent {
     pub name: String,
     pub mime_type: String,
@@ -14,7 +7,7 @@ pub struct UploadFileEvent {
     pub xorname: Option<String>,
 }
 
-#[derive(serde::Serialize, Clone)]
+#[derive(Debug, serde::Serialize, Clone, schemars::JsonSchema)]
 pub struct UploadError {
     pub title: String,
     pub description: String,
@@ -26,25 +19,47 @@ pub struct ToastEvent {
     pub description: String,
 }
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
-pub struct Chunk {
-    #[serde(rename = "type")]
-    pub msg_type: String,
-    pub metadata: ChunkMetadata,
-    pub data: String,
+#[derive(Debug, serde::Serialize, Clone, schemars::JsonSchema)]
+pub struct ServiceStateEvent {
+    pub service: String,
+    pub state: crate::services::ServiceState,
+    pub exit_code: Option<i32>,
+    pub restart_count: u32,
+    pub message: Option<String>,
 }
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
-pub struct ChunkMetadata {
-    pub filename: String,
-    pub mime_type: String,
-    pub chunk_index: usize,
-    pub total_chunks: usize,
-    pub upload_id: String,
+#[derive(serde::Serialize, Clone)]
+pub struct ServiceStatus {
+    pub service: crate::services::Service,
+    pub state: crate::services::ServiceState,
+    pub pid: Option<u32>,
+    pub port: u16,
+    pub uptime_secs: Option<u64>,
+    pub restart_count: u32,
+    pub last_exit_code: Option<i32>,
 }
 
-#[derive(Debug, serde::Deserialize)]
-pub struct DownloadRequest {
-    pub action: String,
-    pub xorname: String,
+#[derive(serde::Serialize, Clone)]
+pub struct ShutdownReport {
+    pub service: crate::services::Service,
+    pub outcome: crate::services::ShutdownOutcome,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct OriginRejectedEvent {
+    pub origin: String,
+    pub route: String,
+    pub timestamp_ms: u64,
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct QuotaExceededEvent {
+    pub client_id: String,
+    pub client_name: String,
+    pub limit: crate::quotas::QuotaLimit,
+    pub allowed: u64,
+    pub error: String,
+    pub timestamp_ms: u64,
 }
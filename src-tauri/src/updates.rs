@@ -0,0 +1,760 @@
+use crate::get_app_data_dir;
+use crate::integrity;
+use crate::logging::LogTarget;
+use crate::services::Service;
+use crate::settings;
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::AppHandle;
+use tokio::io::AsyncWriteExt;
+
+const UPDATE_SETTINGS_KEY: &str = "sidecar-updates";
+
+// target triple the app was built for, set by build.rs
+const TARGET: &str = env!("SAFEBOX_TARGET");
+
+static UPDATE_SETTINGS: Lazy<Mutex<UpdateSettings>> =
+    Lazy::new(|| Mutex::new(UpdateSettings::default()));
+// `--version` of each bundled binary, which can't change while we run
+static BUNDLED_VERSIONS: Lazy<Mutex<HashMap<Service, String>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Published list of sidecar builds, one entry per binary, version and target.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseManifest {
+    pub releases: Vec<Release>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Release {
+    pub service: Service,
+    pub version: String,
+    pub target: String,
+    pub url: String,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpdateSettings {
+    pub manifest_url: Option<String>,
+    pub sidecars: HashMap<Service, SidecarVersions>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SidecarVersions {
+    pub installed: Vec<InstalledVersion>,
+    /// Version to run, `None` runs the bundled binary.
+    pub selected: Option<String>,
+    /// What was selected before, for rolling back.
+    pub previous: Option<String>,
+    /// Pinned sidecars are left alone by `update_sidecar`.
+    pub pinned: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledVersion {
+    pub version: String,
+    pub sha256: String,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AvailableUpdate {
+    pub service: Service,
+    pub selected: Option<String>,
+    pub latest: String,
+    pub installed: bool,
+    pub pinned: bool,
+}
+
+/// The binary `start_server` and friends should run for a sidecar.
+pub struct ActiveBinary {
+    pub path: PathBuf,
+    pub sha256: Option<String>,
+    /// `None` for the bundled binary.
+    pub version: Option<String>,
+}
+
+/// Load the persisted update settings. Called once during setup.
+pub fn init(app: &AppHandle) {
+    if let Some(saved) = settings::load::<UpdateSettings>(app, UPDATE_SETTINGS_KEY) {
+        *UPDATE_SETTINGS.lock().unwrap() = saved;
+    }
+
+    // ask the bundled binaries now rather than during a client handshake
+    std::thread::spawn(|| {
+        for service in Service::ALL {
+            bundled_version(service);
+        }
+    });
+}
+
+pub fn get_settings() -> UpdateSettings {
+    UPDATE_SETTINGS.lock().unwrap().clone()
+}
+
+fn update_settings(
+    app: &AppHandle,
+    change: impl FnOnce(&mut UpdateSettings) -> Result<(), String>,
+) -> Result<(), String> {
+    let updated = {
+        let mut current = UPDATE_SETTINGS.lock().unwrap();
+        let mut updated = current.clone();
+        change(&mut updated)?;
+        *current = updated.clone();
+        updated
+    };
+    settings::save(app, UPDATE_SETTINGS_KEY, &updated)
+}
+
+pub fn set_manifest_url(app: &AppHandle, url: Option<String>) -> Result<(), String> {
+    if let Some(url) = &url {
+        validate_url(url)?;
+    }
+    update_settings(app, |settings| {
+        settings.manifest_url = url;
+        Ok(())
+    })
+}
+
+/// Downloads must be https, plain http is only allowed for a server on this machine.
+fn validate_url(url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid URL '{}': {}", url, e))?;
+    let loopback = matches!(
+        parsed.host_str(),
+        Some("localhost") | Some("127.0.0.1") | Some("[::1]")
+    );
+
+    match parsed.scheme() {
+        "https" => Ok(()),
+        "http" if loopback => Ok(()),
+        scheme => Err(format!("Unsupported URL scheme '{}' in {}", scheme, url)),
+    }
+}
+
+pub fn active_binary(service: Service) -> Result<ActiveBinary, String> {
+    let settings = UPDATE_SETTINGS.lock().unwrap();
+    let versions = settings.sidecars.get(&service);
+
+    let selected = versions.and_then(|versions| {
+        let version = versions.selected.as_ref()?;
+        versions.installed.iter().find(|i| &i.version == version)
+    });
+
+    match selected {
+        Some(installed) => Ok(ActiveBinary {
+            path: PathBuf::from(&installed.path),
+            sha256: Some(installed.sha256.clone()),
+            version: Some(installed.version.clone()),
+        }),
+        None => Ok(ActiveBinary {
+            path: service.binary_path()?,
+            sha256: integrity::bundled_hash(service).map(str::to_string),
+            version: None,
+        }),
+    }
+}
+
+/// The version a sidecar runs, asking the bundled binary when no update is
+/// selected. "unknown" when it can't be told.
+pub fn active_version(service: Service) -> String {
+    match active_binary(service).map(|active| active.version) {
+        Ok(Some(version)) => version,
+        _ => bundled_version(service).unwrap_or_else(|| "unknown".to_string()),
+    }
+}
+
+fn bundled_version(service: Service) -> Option<String> {
+    if let Some(version) = BUNDLED_VERSIONS.lock().unwrap().get(&service) {
+        return Some(version.clone());
+    }
+
+    // never run a binary that doesn't match the bundled hash
+    let report = integrity::check_bundled(service);
+    if !report.ok {
+        return None;
+    }
+    let output = std::process::Command::new(&report.path)
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let version = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()?
+        .trim()
+        .to_string();
+    if version.is_empty() {
+        return None;
+    }
+
+    BUNDLED_VERSIONS
+        .lock()
+        .unwrap()
+        .insert(service, version.clone());
+    Some(version)
+}
+
+/// Whether `path` is the bundled binary or one of the installed versions.
+pub fn is_known_binary(service: Service, path: &Path) -> bool {
+    if service.binary_path().is_ok_and(|bundled| bundled == path) {
+        return true;
+    }
+
+    UPDATE_SETTINGS
+        .lock()
+        .unwrap()
+        .sidecars
+        .get(&service)
+        .is_some_and(|versions| {
+            versions
+                .installed
+                .iter()
+                .any(|i| Path::new(&i.path) == path)
+        })
+}
+
+pub async fn fetch_manifest() -> Result<ReleaseManifest, String> {
+    let url = get_settings()
+        .manifest_url
+        .ok_or("No update manifest URL configured")?;
+    fetch_manifest_from(&url).await
+}
+
+async fn fetch_manifest_from(url: &str) -> Result<ReleaseManifest, String> {
+    validate_url(url)?;
+
+    reqwest::get(url)
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("Failed to fetch {}: {}", url, e))?
+        .json::<ReleaseManifest>()
+        .await
+        .map_err(|e| format!("Invalid release manifest at {}: {}", url, e))
+}
+
+fn latest_release(manifest: &ReleaseManifest, service: Service) -> Option<&Release> {
+    manifest
+        .releases
+        .iter()
+        .filter(|release| release.service == service && release.target == TARGET)
+        .max_by(|a, b| compare_versions(&a.version, &b.version))
+}
+
+pub async fn check() -> Result<Vec<AvailableUpdate>, String> {
+    let manifest = fetch_manifest().await?;
+    let settings = get_settings();
+
+    let updates = Service::ALL
+        .into_iter()
+        .filter_map(|service| {
+            let latest = latest_release(&manifest, service)?;
+            let versions = settings.sidecars.get(&service).cloned().unwrap_or_default();
+
+            Some(AvailableUpdate {
+                service,
+                selected: versions.selected.clone(),
+                latest: latest.version.clone(),
+                installed: versions
+                    .installed
+                    .iter()
+                    .any(|i| i.version == latest.version),
+                pinned: versions.pinned,
+            })
+        })
+        .collect();
+
+    Ok(updates)
+}
+
+/// Download and verify a version into the app data dir. It isn't selected yet.
+pub async fn install(
+    app: &AppHandle,
+    service: Service,
+    version: &str,
+) -> Result<InstalledVersion, String> {
+    let manifest = fetch_manifest().await?;
+    let release = find_release(&manifest, service, version)?;
+    let installed = download_release(&release, &get_app_data_dir()?.join("sidecars")).await?;
+
+    update_settings(app, |settings| {
+        let versions = settings.sidecars.entry(service).or_default();
+        versions
+            .installed
+            .retain(|i| i.version != installed.version);
+        versions.installed.push(installed.clone());
+        Ok(())
+    })?;
+
+    Ok(installed)
+}
+
+/// The published build of `version` for this target, if it is safe to fetch.
+fn find_release(
+    manifest: &ReleaseManifest,
+    service: Service,
+    version: &str,
+) -> Result<Release, String> {
+    let release = manifest
+        .releases
+        .iter()
+        .find(|r| r.service == service && r.version == version && r.target == TARGET)
+        .ok_or_else(|| {
+            format!(
+                "{} {} is not published for {}",
+                service.name(),
+                version,
+                TARGET
+            )
+        })?;
+    validate_url(&release.url)?;
+
+    // the version ends up in a path, keep it to something harmless
+    if release.version.is_empty()
+        || !release
+            .version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+' | '_'))
+        || release.version.starts_with('.')
+    {
+        return Err(format!("Refusing odd version string '{}'", release.version));
+    }
+
+    Ok(release.clone())
+}
+
+/// Fetch a release into `<root>/<service>/<version>/` and check its digest.
+async fn download_release(release: &Release, root: &Path) -> Result<InstalledVersion, String> {
+    let service = release.service;
+    let dir = root.join(service.name()).join(&release.version);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    let mut file_name = service.name().to_string();
+    if cfg!(windows) {
+        file_name.push_str(".exe");
+    }
+    let path = dir.join(file_name);
+    // installs running at the same time each get their own file, it is
+    // removed when dropped unless it was moved into place
+    let partial = tempfile::Builder::new()
+        .prefix("download")
+        .suffix(".partial")
+        .tempfile_in(&dir)
+        .map_err(|e| format!("Failed to create a file in {}: {}", dir.display(), e))?;
+
+    log_info!(
+        LogTarget::Backend,
+        "Downloading {} {} from {}",
+        service.name(),
+        release.version,
+        release.url
+    );
+
+    let sha256 = download(&release.url, partial.path()).await?;
+    if !sha256.eq_ignore_ascii_case(&release.sha256) {
+        return Err(format!(
+            "Checksum mismatch for {} {}: expected {}, got {}",
+            service.name(),
+            release.version,
+            release.sha256,
+            sha256
+        ));
+    }
+
+    partial
+        .persist(&path)
+        .map_err(|e| format!("Failed to move download into place: {}", e))?;
+    make_executable(&path)?;
+
+    Ok(InstalledVersion {
+        version: release.version.clone(),
+        sha256,
+        path: path.to_string_lossy().to_string(),
+    })
+}
+
+async fn download(url: &str, path: &Path) -> Result<String, String> {
+    let response = reqwest::get(url)
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("Failed to download {}: {}", url, e))?;
+
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut stream = response.bytes_stream();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| format!("Download of {} failed: {}", url, e))?;
+        hasher.update(&chunk);
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+    file.flush()
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+        .map_err(|e| format!("Failed to mark {} executable: {}", path.display(), e))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+/// Choose which version runs on the next start. `None` goes back to the bundled binary.
+pub fn select(
+    app: &AppHandle,
+    service: Service,
+    version: Option<String>,
+    pin: bool,
+) -> Result<(), String> {
+    update_settings(app, |settings| {
+        let versions = settings.sidecars.entry(service).or_default();
+
+        if let Some(version) = &version {
+            if !versions.installed.iter().any(|i| &i.version == version) {
+                return Err(format!("{} {} is not installed", service.name(), version));
+            }
+        }
+
+        if versions.selected != version {
+            versions.previous = versions.selected.take();
+            versions.selected = version;
+        }
+        versions.pinned = pin;
+        Ok(())
+    })
+}
+
+/// Swap back to the previously selected version. Returns what is selected now.
+pub fn rollback(app: &AppHandle, service: Service) -> Result<Option<String>, String> {
+    let mut selected = None;
+
+    update_settings(app, |settings| {
+        let versions = settings.sidecars.entry(service).or_default();
+        if versions.previous.is_none() && versions.selected.is_none() {
+            return Err(format!("Nothing to roll back for {}", service.name()));
+        }
+
+        // a previous version that was removed meanwhile falls back to bundled
+        let previous = versions
+            .previous
+            .take()
+            .filter(|previous| versions.installed.iter().any(|i| &i.version == previous));
+        versions.previous = versions.selected.take();
+        versions.selected = previous;
+        selected = versions.selected.clone();
+        Ok(())
+    })?;
+
+    Ok(selected)
+}
+
+/// Install and select the newest published version, unless the sidecar is pinned.
+pub async fn update(app: &AppHandle, service: Service) -> Result<Option<String>, String> {
+    let versions = get_settings()
+        .sidecars
+        .get(&service)
+        .cloned()
+        .unwrap_or_default();
+    if versions.pinned {
+        return Err(format!(
+            "{} is pinned to {}",
+            service.name(),
+            versions
+                .selected
+                .as_deref()
+                .unwrap_or("the bundled version")
+        ));
+    }
+
+    let manifest = fetch_manifest().await?;
+    let Some(latest) = latest_release(&manifest, service).cloned() else {
+        return Ok(None);
+    };
+    if !is_newer(&latest.version, versions.selected.as_deref()) {
+        return Ok(None);
+    }
+
+    if !versions
+        .installed
+        .iter()
+        .any(|i| i.version == latest.version)
+    {
+        install(app, service, &latest.version).await?;
+    }
+    select(app, service, Some(latest.version.clone()), false)?;
+
+    Ok(Some(latest.version))
+}
+
+/// Whether `latest` should replace `selected`. Never a downgrade, and the
+/// bundled binary (`None`) gives way to any published version.
+fn is_newer(latest: &str, selected: Option<&str>) -> bool {
+    match selected {
+        Some(selected) => compare_versions(latest, selected) == Ordering::Greater,
+        None => true,
+    }
+}
+
+/// Dotted numeric comparison, falling back to plain string order for
+/// components that aren't numbers. A pre-release sorts below its release and
+/// build metadata is ignored ("1.10.0" > "1.9.2", "1.0.0-rc1" < "1.0.0-rc2" < "1.0.0").
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (a_release, a_pre) = split_version(a);
+    let (b_release, b_pre) = split_version(b);
+
+    compare_parts(a_release, b_release).then_with(|| match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(a), Some(b)) => compare_parts(a, b),
+    })
+}
+
+/// "1.0.0-rc1+build5" into "1.0.0" and "rc1".
+fn split_version(version: &str) -> (&str, Option<&str>) {
+    let version = version.split('+').next().unwrap_or_default();
+    match version.split_once('-') {
+        Some((release, pre)) => (release, Some(pre)),
+        None => (version, None),
+    }
+}
+
+fn compare_parts(a: &str, b: &str) -> Ordering {
+    let mut a_parts = a.split(['.', '-']);
+    let mut b_parts = b.split(['.', '-']);
+
+    loop {
+        match (a_parts.next(), b_parts.next()) {
+            (None, None) => return Ordering::Equal,
+            (Some(_), None) => return Ordering::Greater,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(a), Some(b)) => {
+                let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+                    (Ok(a), Ok(b)) => a.cmp(&b),
+                    _ => a.cmp(b),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use warp::Filter;
+
+    const ARTIFACT: &[u8] = b"#!/bin/sh\necho ant\n";
+
+    fn artifact_sha256() -> String {
+        format!("{:x}", Sha256::digest(ARTIFACT))
+    }
+
+    fn release(base: &str, service: Service, version: &str, sha256: &str) -> Release {
+        Release {
+            service,
+            version: version.to_string(),
+            target: TARGET.to_string(),
+            url: format!("{}/artifacts/{}-{}", base, service.name(), version),
+            sha256: sha256.to_string(),
+        }
+    }
+
+    /// Serve the manifest built by `releases` at `/manifest.json` and
+    /// `ARTIFACT` for every `/artifacts/...`. Returns the base URL.
+    async fn serve(releases: impl FnOnce(&str) -> Vec<Release>) -> String {
+        let manifest = Arc::new(Mutex::new(String::new()));
+
+        let body = manifest.clone();
+        let manifest_route = warp::path!("manifest.json").map(move || {
+            warp::reply::with_header(
+                body.lock().unwrap().clone(),
+                "content-type",
+                "application/json",
+            )
+        });
+        let artifact_route =
+            warp::path!("artifacts" / String).map(|_name: String| ARTIFACT.to_vec());
+
+        let (addr, server) =
+            warp::serve(manifest_route.or(artifact_route)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let base = format!("http://127.0.0.1:{}", addr.port());
+        *manifest.lock().unwrap() = serde_json::to_string(&ReleaseManifest {
+            releases: releases(&base),
+        })
+        .unwrap();
+        base
+    }
+
+    fn files_in(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn pre_releases_sort_below_their_release() {
+        assert_eq!(compare_versions("1.0.0-rc1", "1.0.0"), Ordering::Less);
+        assert_eq!(compare_versions("1.0.0-rc1", "1.0.0-rc2"), Ordering::Less);
+        assert_eq!(
+            compare_versions("1.0.0-rc.10", "1.0.0-rc.9"),
+            Ordering::Greater
+        );
+        assert_eq!(compare_versions("1.10.0", "1.9.2"), Ordering::Greater);
+        assert_eq!(compare_versions("1.1.0-rc1", "1.0.0"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0.0+build5", "1.0.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn updates_never_downgrade() {
+        assert!(is_newer("1.10.0", Some("1.9.2")));
+        assert!(is_newer("1.0.0", Some("1.0.0-rc1")));
+        assert!(is_newer("1.0.0", None));
+        assert!(!is_newer("1.0.0", Some("1.0.0")));
+        assert!(!is_newer("1.9.2", Some("1.10.0")));
+        assert!(!is_newer("1.0.0-rc1", Some("1.0.0")));
+    }
+
+    #[tokio::test]
+    async fn picks_the_latest_release_for_this_target() {
+        let sha256 = artifact_sha256();
+        let base = serve(|base| {
+            let mut other_target = release(base, Service::Ant, "9.0.0", &sha256);
+            other_target.target = "not-this-target".to_string();
+            vec![
+                release(base, Service::Ant, "1.9.2", &sha256),
+                release(base, Service::Ant, "1.10.0", &sha256),
+                release(base, Service::Ant, "1.10.1-rc1", &sha256),
+                release(base, Service::Anttp, "5.0.0", &sha256),
+                other_target,
+            ]
+        })
+        .await;
+
+        let manifest = fetch_manifest_from(&format!("{}/manifest.json", base))
+            .await
+            .unwrap();
+        let latest = latest_release(&manifest, Service::Ant).unwrap();
+        assert_eq!(latest.version, "1.10.0");
+        assert!(latest_release(&manifest, Service::Dweb).is_none());
+    }
+
+    #[tokio::test]
+    async fn installs_a_verified_download() {
+        let sha256 = artifact_sha256();
+        let base = serve(|base| vec![release(base, Service::Ant, "1.2.3", &sha256)]).await;
+        let manifest = fetch_manifest_from(&format!("{}/manifest.json", base))
+            .await
+            .unwrap();
+        let root = tempfile::tempdir().unwrap();
+
+        let release = find_release(&manifest, Service::Ant, "1.2.3").unwrap();
+        let installed = download_release(&release, root.path()).await.unwrap();
+
+        assert_eq!(installed.sha256, sha256);
+        assert_eq!(std::fs::read(&installed.path).unwrap(), ARTIFACT);
+        let dir = root.path().join("ant").join("1.2.3");
+        assert_eq!(files_in(&dir).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refuses_a_checksum_mismatch() {
+        let wrong = format!("{:x}", Sha256::digest(b"something else"));
+        let base = serve(|base| vec![release(base, Service::Ant, "1.2.3", &wrong)]).await;
+        let manifest = fetch_manifest_from(&format!("{}/manifest.json", base))
+            .await
+            .unwrap();
+        let root = tempfile::tempdir().unwrap();
+
+        let release = find_release(&manifest, Service::Ant, "1.2.3").unwrap();
+        let error = download_release(&release, root.path()).await.unwrap_err();
+
+        assert!(error.contains("Checksum mismatch"), "{}", error);
+        // neither the binary nor the partial download is left behind
+        assert!(files_in(&root.path().join("ant").join("1.2.3")).is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_installs_use_separate_downloads() {
+        let sha256 = artifact_sha256();
+        let base = serve(|base| vec![release(base, Service::Ant, "1.2.3", &sha256)]).await;
+        let manifest = fetch_manifest_from(&format!("{}/manifest.json", base))
+            .await
+            .unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let release = find_release(&manifest, Service::Ant, "1.2.3").unwrap();
+
+        let (first, second) = tokio::join!(
+            download_release(&release, root.path()),
+            download_release(&release, root.path())
+        );
+
+        let installed = first.unwrap();
+        second.unwrap();
+        assert_eq!(std::fs::read(&installed.path).unwrap(), ARTIFACT);
+        assert_eq!(files_in(&root.path().join("ant").join("1.2.3")).len(), 1);
+    }
+
+    #[test]
+    fn rejects_bad_urls_and_versions() {
+        let sha256 = artifact_sha256();
+        let remote_http = release("http://example.com", Service::Ant, "1.0.0", &sha256);
+        let file_url = Release {
+            url: "file:///etc/passwd".to_string(),
+            ..release("", Service::Ant, "1.0.1", &sha256)
+        };
+        let manifest = ReleaseManifest {
+            releases: vec![
+                remote_http,
+                file_url,
+                release("https://example.com", Service::Ant, "../../evil", &sha256),
+                release("https://example.com", Service::Ant, ".hidden", &sha256),
+                release("https://example.com", Service::Ant, "1.0.2", &sha256),
+            ],
+        };
+
+        assert!(find_release(&manifest, Service::Ant, "1.0.0").is_err());
+        assert!(find_release(&manifest, Service::Ant, "1.0.1").is_err());
+        assert!(find_release(&manifest, Service::Ant, "../../evil").is_err());
+        assert!(find_release(&manifest, Service::Ant, ".hidden").is_err());
+        assert!(find_release(&manifest, Service::Ant, "9.9.9").is_err());
+        assert!(find_release(&manifest, Service::Ant, "1.0.2").is_ok());
+
+        assert!(validate_url("http://127.0.0.1:8080/manifest.json").is_ok());
+        assert!(validate_url("ftp://example.com/manifest.json").is_err());
+        assert!(validate_url("not a url").is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! Binary upload chunks, an alternative to JSON frames with base64 `data`.
+//!
+//! Every field is big-endian:
+//!
+//! ```text
+//! u8   version (1)
+//! u8   flags, bit 0 set when file metadata follows the header
+//! u16  upload_id length, then the upload_id as UTF-8
+//! u32  chunk_index
+//! u32  total_chunks
+//! [u16 filename length, filename, u16 mime_type length, mime_type]
+//! ...  the raw chunk bytes, up to the end of the frame
+//! ```
+//!
+//! The metadata is required on chunk 0 and may be left out of the others.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+const FRAME_VERSION: u8 = 1;
+const FLAG_METADATA: u8 = 0b0000_0001;
+
+/// How a connection sends its upload chunks, picked with `?format=` on the upgrade.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkFormat {
+    Json,
+    Binary,
+}
+
+impl ChunkFormat {
+    /// JSON when the client doesn't ask, so older extensions keep working.
+    pub fn parse(format: Option<&str>) -> Result<Self, String> {
+        match format {
+            None | Some("json") => Ok(ChunkFormat::Json),
+            Some("binary") => Ok(ChunkFormat::Binary),
+            Some(other) => Err(format!("Unknown chunk format '{}'", other)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ChunkFormat::Json => "json",
+            ChunkFormat::Binary => "binary",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileInfo {
+    pub filename: String,
+    pub mime_type: String,
+}
+
+/// One chunk, whichever format it arrived in.
+#[derive(Debug)]
+pub struct ReceivedChunk {
+    pub upload_id: String,
+    pub chunk_index: usize,
+    pub total_chunks: usize,
+    pub file: Option<FileInfo>,
+    pub data: Vec<u8>,
+}
+
+struct Reader<'a> {
+    frame: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize, field: &str) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.frame.len())
+            .ok_or_else(|| format!("Frame too short for {}", field))?;
+        let bytes = &self.frame[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self, field: &str) -> Result<u8, String> {
+        Ok(self.take(1, field)?[0])
+    }
+
+    fn u16(&mut self, field: &str) -> Result<u16, String> {
+        let bytes = self.take(2, field)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self, field: &str) -> Result<u32, String> {
+        let bytes = self.take(4, field)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self, field: &str) -> Result<String, String> {
+        let len = self.u16(field)? as usize;
+        let bytes = self.take(len, field)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| format!("{} is not valid UTF-8", field))
+    }
+}
+
+/// Parse a binary frame. The chunk bytes reuse the frame's buffer.
+pub fn decode(mut frame: Vec<u8>) -> Result<ReceivedChunk, String> {
+    let mut reader = Reader {
+        frame: &frame,
+        pos: 0,
+    };
+
+    let version = reader.u8("version")?;
+    if version != FRAME_VERSION {
+        return Err(format!("Unsupported chunk frame version {}", version));
+    }
+    let flags = reader.u8("flags")?;
+    let upload_id = reader.string("upload_id")?;
+    let chunk_index = reader.u32("chunk_index")? as usize;
+    let total_chunks = reader.u32("total_chunks")? as usize;
+
+    let file = if flags & FLAG_METADATA != 0 {
+        Some(FileInfo {
+            filename: reader.string("filename")?,
+            mime_type: reader.string("mime_type")?,
+        })
+    } else {
+        None
+    };
+
+    if chunk_index == 0 && file.is_none() {
+        return Err("Chunk 0 must carry the file metadata".into());
+    }
+
+    let header_len = reader.pos;
+    frame.drain(..header_len);
+
+    Ok(ReceivedChunk {
+        upload_id,
+        chunk_index,
+        total_chunks,
+        file,
+        data: frame,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_string(frame: &mut Vec<u8>, value: &[u8]) {
+        frame.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        frame.extend_from_slice(value);
+    }
+
+    fn frame(chunk_index: u32, file: Option<(&[u8], &[u8])>, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![
+            FRAME_VERSION,
+            if file.is_some() { FLAG_METADATA } else { 0 },
+        ];
+        push_string(&mut frame, b"upload-1");
+        frame.extend_from_slice(&chunk_index.to_be_bytes());
+        frame.extend_from_slice(&3u32.to_be_bytes());
+        if let Some((filename, mime_type)) = file {
+            push_string(&mut frame, filename);
+            push_string(&mut frame, mime_type);
+        }
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    #[test]
+    fn decodes_what_a_client_encodes() {
+        let chunk = decode(frame(0, Some((b"notes.txt", b"text/plain")), b"hello")).unwrap();
+        assert_eq!(chunk.upload_id, "upload-1");
+        assert_eq!(chunk.chunk_index, 0);
+        assert_eq!(chunk.total_chunks, 3);
+        let file = chunk.file.unwrap();
+        assert_eq!(file.filename, "notes.txt");
+        assert_eq!(file.mime_type, "text/plain");
+        assert_eq!(chunk.data, b"hello");
+
+        let chunk = decode(frame(2, None, &[0, 1, 2])).unwrap();
+        assert_eq!(chunk.chunk_index, 2);
+        assert!(chunk.file.is_none());
+        assert_eq!(chunk.data, [0, 1, 2]);
+    }
+
+    #[test]
+    fn refuses_truncated_headers() {
+        let full = frame(0, Some((b"notes.txt", b"text/plain")), b"");
+        for len in 0..full.len() {
+            let error = decode(full[..len].to_vec()).unwrap_err();
+            assert!(error.starts_with("Frame too short"), "{}: {}", len, error);
+        }
+    }
+
+    #[test]
+    fn refuses_other_versions() {
+        let mut frame = frame(1, None, b"data");
+        frame[0] = 2;
+        assert_eq!(
+            decode(frame).unwrap_err(),
+            "Unsupported chunk frame version 2"
+        );
+    }
+
+    #[test]
+    fn chunk_zero_needs_metadata() {
+        assert_eq!(
+            decode(frame(0, None, b"data")).unwrap_err(),
+            "Chunk 0 must carry the file metadata"
+        );
+    }
+
+    #[test]
+    fn refuses_a_filename_that_is_not_utf8() {
+        let frame = frame(0, Some((&[0x6e, 0xff, 0xfe], b"text/plain")), b"data");
+        assert_eq!(decode(frame).unwrap_err(), "filename is not valid UTF-8");
+    }
+}
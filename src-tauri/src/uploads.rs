@@ -0,0 +1,222 @@
+use crate::events::{self, PushEvent, UploadCompleted};
+use crate::logging::LogTarget;
+use crate::settings;
+use crate::types::{UploadError, UploadFileEvent};
+use crate::upload_frames::FileInfo;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter};
+
+const UPLOADED_FILES_KEY: &str = "uploaded-files";
+
+// finished uploads kept around for status queries
+const MAX_UPLOAD_RECORDS: usize = 500;
+const MAX_UPLOADED_FILES: usize = 1000;
+
+static UPLOADS: Lazy<Mutex<VecDeque<UploadRecord>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+static UPLOADED_FILES: Lazy<Mutex<Vec<UploadedFile>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadState {
+    Receiving,
+    Uploading,
+    Complete,
+    Failed,
+}
+
+/// An upload from a websocket or REST client, from the first byte on.
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadRecord {
+    pub id: String,
+    pub client_id: String,
+    pub name: String,
+    pub mime_type: String,
+    pub size: Option<u64>,
+    pub state: UploadState,
+    pub xorname: Option<String>,
+    pub error: Option<String>,
+    pub started_ms: u64,
+    pub finished_ms: Option<u64>,
+}
+
+impl UploadRecord {
+    fn is(&self, id: &str, client_id: &str) -> bool {
+        self.id == id && self.client_id == client_id
+    }
+}
+
+/// A file stored on the network through SafeBox.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadedFile {
+    pub xorname: String,
+    pub name: String,
+    pub mime_type: String,
+    pub size: u64,
+    pub client_id: String,
+    pub uploaded_ms: u64,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Load the list of uploaded files. Called once during setup.
+pub fn init(app: &AppHandle) {
+    if let Some(saved) = settings::load::<Vec<UploadedFile>>(app, UPLOADED_FILES_KEY) {
+        *UPLOADED_FILES.lock().unwrap() = saved;
+    }
+}
+
+/// Start tracking an upload. A record the same client made with the same id
+/// is replaced. Websocket clients pick their own ids, so these are only
+/// unique per client.
+pub fn begin(id: &str, client_id: &str, file: &FileInfo, state: UploadState) {
+    let mut uploads = UPLOADS.lock().unwrap();
+    uploads.retain(|record| !record.is(id, client_id));
+    if uploads.len() >= MAX_UPLOAD_RECORDS {
+        uploads.pop_front();
+    }
+    uploads.push_back(UploadRecord {
+        id: id.to_string(),
+        client_id: client_id.to_string(),
+        name: file.filename.clone(),
+        mime_type: file.mime_type.clone(),
+        size: None,
+        state,
+        xorname: None,
+        error: None,
+        started_ms: now_ms(),
+        finished_ms: None,
+    });
+}
+
+fn update(id: &str, client_id: &str, apply: impl FnOnce(&mut UploadRecord)) {
+    let mut uploads = UPLOADS.lock().unwrap();
+    if let Some(record) = uploads.iter_mut().find(|record| record.is(id, client_id)) {
+        apply(record);
+    }
+}
+
+pub fn fail(id: &str, client_id: &str, error: &str) {
+    update(id, client_id, |record| {
+        record.state = UploadState::Failed;
+        record.error = Some(error.to_string());
+        record.finished_ms = Some(now_ms());
+    });
+}
+
+/// An upload as seen by the client that sent it.
+pub fn get(id: &str, client_id: &str) -> Option<UploadRecord> {
+    UPLOADS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|record| record.is(id, client_id))
+        .cloned()
+}
+
+/// Files a client stored, like its uploads no other client sees them.
+pub fn files(client_id: &str) -> Vec<UploadedFile> {
+    UPLOADED_FILES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|file| file.client_id == client_id)
+        .cloned()
+        .collect()
+}
+
+pub fn file(xorname: &str, client_id: &str) -> Option<UploadedFile> {
+    UPLOADED_FILES
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|file| file.xorname == xorname && file.client_id == client_id)
+        .cloned()
+}
+
+/// Put a fully received file on the network, then tell the UI, the
+/// subscribers and the registry how it went.
+pub async fn store(
+    app: &AppHandle,
+    id: &str,
+    client_id: &str,
+    file: &FileInfo,
+    path: &Path,
+) -> Result<String, String> {
+    let size = tokio::fs::metadata(path)
+        .await
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    update(id, client_id, |record| {
+        record.state = UploadState::Uploading;
+        record.size = Some(size);
+    });
+
+    let result = crate::upload_path(path, app).await;
+
+    let event = UploadFileEvent {
+        name: file.filename.clone(),
+        mime_type: file.mime_type.clone(),
+        xorname: result.as_ref().ok().cloned(),
+        success: result.is_ok(),
+        error: result.as_ref().err().map(|error| UploadError {
+            title: "Upload Failed".into(),
+            description: format!("{:?}", error),
+        }),
+    };
+    let _ = app.emit("upload-file", event.clone());
+    events::publish(PushEvent::UploadsCompleted(UploadCompleted {
+        upload_id: id.to_string(),
+        client_id: client_id.to_string(),
+        file: event,
+    }));
+
+    match &result {
+        Ok(xorname) => {
+            update(id, client_id, |record| {
+                record.state = UploadState::Complete;
+                record.xorname = Some(xorname.clone());
+                record.finished_ms = Some(now_ms());
+            });
+            remember_file(
+                app,
+                UploadedFile {
+                    xorname: xorname.clone(),
+                    name: file.filename.clone(),
+                    mime_type: file.mime_type.clone(),
+                    size,
+                    client_id: client_id.to_string(),
+                    uploaded_ms: now_ms(),
+                },
+            );
+        }
+        Err(e) => fail(id, client_id, e),
+    }
+
+    result
+}
+
+fn remember_file(app: &AppHandle, file: UploadedFile) {
+    let files = {
+        let mut files = UPLOADED_FILES.lock().unwrap();
+        files.retain(|known| known.xorname != file.xorname);
+        files.push(file);
+        if files.len() > MAX_UPLOADED_FILES {
+            let excess = files.len() - MAX_UPLOADED_FILES;
+            files.drain(..excess);
+        }
+        files.clone()
+    };
+    if let Err(e) = settings::save(app, UPLOADED_FILES_KEY, &files) {
+        log_warn!(LogTarget::Backend, "Failed to save uploaded files: {}", e);
+    }
+}
@@ -1,23 +1,51 @@
-use crate::do_upload;
-use crate::types::{
-    Chunk, DownloadRequest, ToastEvent, UploadError, UploadFileEvent, UploadFilePayload,
+use crate::events::{self, NetworkStatus, PushEvent, Topic, UploadProgress};
+use crate::logging::LogTarget;
+use crate::network;
+use crate::origins;
+use crate::pairing::{self, PairRequest, PairedClient};
+use crate::protocol::{
+    self, ChunkMetadata, ClientMessage, ErrorCode, LegacyChunk, LegacyDownloadRequest,
+    ServerMessage, Session,
 };
+use crate::quotas::{self, ChunkUsage, QuotaExceeded, QuotaLimit};
+use crate::rest_api;
+use crate::services::{self, Service, ServiceState};
+use crate::types::ToastEvent;
+use crate::upload_frames::{self, ChunkFormat, FileInfo, ReceivedChunk};
+use crate::uploads::{self, UploadState};
 use crate::{ANTTP_PORT, DWEB_PORT, WEBSOCKET_PORT};
 use base64::decode;
-use dirs::data_dir;
+use futures::stream::{SplitSink, SplitStream};
 use futures::{stream::StreamExt, SinkExt};
 use once_cell::sync::Lazy;
 use serde_json::json;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tauri::AppHandle;
 use tauri::Emitter;
+use tempfile::NamedTempFile;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::{watch, Mutex};
+use warp::http::StatusCode;
 use warp::ws::{Message, WebSocket};
-use warp::Filter;
+use warp::{Filter, Reply};
+
+// websocket close code for a refused client
+const POLICY_VIOLATION: u16 = 1008;
+// application close code for a client whose protocol version we don't speak
+const INCOMPATIBLE_PROTOCOL: u16 = 4000;
+
+// unfinished uploads are kept this long after their last chunk, for resuming
+const PENDING_UPLOAD_TTL: Duration = Duration::from_secs(15 * 60);
+
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
 
 pub static WEBSOCKET_SHUTDOWN_TX: Lazy<Mutex<Option<watch::Sender<bool>>>> =
     Lazy::new(|| Mutex::new(None));
@@ -44,63 +72,401 @@ pub async fn stop_websocket_server() -> Result<(), String> {
     Ok(())
 }
 
-async fn handle_root_ws(ws: WebSocket) {
+/// The root socket pushes events of the topics a client subscribed to.
+/// Version 1 clients can't subscribe and are only kept connected.
+async fn handle_root_ws(ws: WebSocket, handle: AppHandle, client: PairedClient) {
     let (mut tx, mut rx) = ws.split();
 
+    log_info!(LogTarget::Websocket, "{} connected", client.name);
+
+    let Some(mut revocation) = pairing::revocation(&client.id) else {
+        close_revoked(&mut tx, &client).await;
+        return;
+    };
+    let Some((session, first)) = handshake(
+        &mut tx,
+        &mut rx,
+        &handle,
+        &client,
+        &mut revocation,
+        ChunkFormat::Json,
+    )
+    .await
+    else {
+        return;
+    };
+    if session.is_legacy() {
+        loop {
+            tokio::select! {
+                msg = rx.next() => if !matches!(msg, Some(Ok(_))) {
+                    return;
+                },
+                _ = revoked(&mut revocation) => {
+                    close_revoked(&mut tx, &client).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    let mut events = events::subscribe();
+    let mut topics: HashSet<Topic> = HashSet::new();
+    let mut pending = first;
+
+    loop {
+        let msg = match pending.take() {
+            Some(msg) => msg,
+            None => tokio::select! {
+                msg = rx.next() => match msg {
+                    Some(Ok(msg)) => msg,
+                    _ => break,
+                },
+                event = events.recv() => {
+                    let message = match event {
+                        Ok(event) if topics.contains(&event.topic()) => ServerMessage::Event(event),
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(missed)) => ServerMessage::Error {
+                            code: ErrorCode::EventsLost,
+                            error: format!("{} events were dropped", missed),
+                        },
+                        Err(RecvError::Closed) => break,
+                    };
+                    if tx.send(reply(&session, &message)).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                _ = revoked(&mut revocation) => {
+                    close_revoked(&mut tx, &client).await;
+                    break;
+                }
+            },
+        };
+
+        let Ok(text) = msg.to_str() else {
+            continue;
+        };
+        let response = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(ClientMessage::Subscribe(request)) => {
+                let added: Vec<Topic> = request
+                    .topics
+                    .into_iter()
+                    .filter(|topic| topics.insert(*topic))
+                    .collect();
+                // a new subscriber starts from the current state
+                for event in current_state(&added) {
+                    let _ = tx.send(reply(&session, &ServerMessage::Event(event))).await;
+                }
+                ServerMessage::Subscribed {
+                    topics: topics.iter().copied().collect(),
+                }
+            }
+            Ok(ClientMessage::Unsubscribe(request)) => {
+                for topic in &request.topics {
+                    topics.remove(topic);
+                }
+                ServerMessage::Subscribed {
+                    topics: topics.iter().copied().collect(),
+                }
+            }
+            Ok(other) => ServerMessage::Error {
+                code: ErrorCode::UnexpectedMessage,
+                error: format!("{} is not expected on the root socket", other.kind()),
+            },
+            Err(e) => ServerMessage::Error {
+                code: ErrorCode::InvalidMessage,
+                error: e.to_string(),
+            },
+        };
+        if tx.send(reply(&session, &response)).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Snapshots of the topics that describe a state rather than a happening.
+fn current_state(topics: &[Topic]) -> Vec<PushEvent> {
+    let mut snapshot = Vec::new();
+    if topics.contains(&Topic::ServiceStatus) {
+        for service in Service::ALL {
+            snapshot.push(PushEvent::ServiceStatus(services::state_event(
+                service, None,
+            )));
+        }
+    }
+    if topics.contains(&Topic::NetworkStatus) {
+        snapshot.push(PushEvent::NetworkStatus(NetworkStatus {
+            online: services::state_event(Service::Ant, None).state == ServiceState::Ready,
+        }));
+    }
+    snapshot
+}
+
+/// Greet the client with our hello and read its own. A client whose first
+/// message isn't a hello speaks protocol version 1, and that message is
+/// handed back to be processed. `None` once the connection is done for,
+/// including when the client is revoked before saying hello.
+async fn handshake(
+    tx: &mut SplitSink<WebSocket, Message>,
+    rx: &mut SplitStream<WebSocket>,
+    handle: &AppHandle,
+    client: &PairedClient,
+    revocation: &mut watch::Receiver<bool>,
+    default_format: ChunkFormat,
+) -> Option<(Session, Option<Message>)> {
+    let hello = protocol::server_hello(handle).to_json();
+    tx.send(Message::text(hello)).await.ok()?;
+
+    let first = loop {
+        let msg = tokio::select! {
+            msg = rx.next() => msg,
+            _ = revoked(revocation) => {
+                close_revoked(tx, client).await;
+                return None;
+            }
+        };
+        match msg {
+            Some(Ok(msg)) if msg.is_text() || msg.is_binary() => break msg,
+            Some(Ok(_)) => continue,
+            _ => return None,
+        }
+    };
+
+    // anything tagged as a hello is held to the schema, a typo shouldn't
+    // quietly downgrade the client to version 1
+    let hello = first
+        .to_str()
+        .ok()
+        .and_then(|text| serde_json::from_str::<serde_json::Value>(text).ok())
+        .filter(|value| value["type"] == "hello");
+    let Some(hello) = hello else {
+        return Some((Session::legacy(default_format), Some(first)));
+    };
+
+    let negotiated = match serde_json::from_value::<ClientMessage>(hello) {
+        Ok(ClientMessage::Hello(hello)) => {
+            log_info!(
+                LogTarget::Websocket,
+                "Client {} speaks protocol version {}",
+                hello.client_version.as_deref().unwrap_or("(unknown)"),
+                hello.protocol_version
+            );
+            protocol::negotiate(&hello, default_format)
+                .map_err(|e| (ErrorCode::IncompatibleProtocol, e))
+        }
+        Ok(other) => Err((
+            ErrorCode::UnexpectedMessage,
+            format!("Expected a hello, got {}", other.kind()),
+        )),
+        Err(e) => Err((ErrorCode::InvalidMessage, format!("Invalid hello: {}", e))),
+    };
+
+    match negotiated {
+        Ok(session) => {
+            let ready = ServerMessage::Ready {
+                protocol_version: session.protocol_version,
+                chunk_format: session.chunk_format,
+            };
+            tx.send(reply(&session, &ready)).await.ok()?;
+            Some((session, None))
+        }
+        Err((code, error)) => {
+            log_warn!(LogTarget::Websocket, "Handshake failed: {}", error);
+            let error = ServerMessage::Error { code, error };
+            let _ = tx.send(Message::text(error.to_json())).await;
+            let _ = tx
+                .send(Message::close_with(
+                    INCOMPATIBLE_PROTOCOL,
+                    "Handshake failed",
+                ))
+                .await;
+            None
+        }
+    }
+}
+
+/// Resolves once the client is revoked.
+async fn revoked(revocation: &mut watch::Receiver<bool>) {
+    // the sender only goes away on revocation too
+    let _ = revocation.wait_for(|revoked| *revoked).await;
+}
+
+async fn close_revoked(tx: &mut SplitSink<WebSocket, Message>, client: &PairedClient) {
+    log_info!(
+        LogTarget::Websocket,
+        "Closing connection of revoked client {}",
+        client.name
+    );
     let _ = tx
-        .send(Message::text("Connected to SafeBox Client WebSocket"))
+        .send(Message::close_with(POLICY_VIOLATION, "Client was revoked"))
         .await;
+}
 
-    while let Some(Ok(_msg)) = rx.next().await {
-        // Ignore all messages on root WS
-    }
+fn reply(session: &Session, message: &ServerMessage) -> Message {
+    Message::text(session.encode(message))
 }
 
-pub async fn start_websocket_server(handle: AppHandle, mut shutdown_rx: watch::Receiver<bool>) {
+pub async fn start_websocket_server(handle: AppHandle, shutdown_rx: watch::Receiver<bool>) {
     let port = *WEBSOCKET_PORT.lock().unwrap();
 
     let upload_handle = handle.clone();
     let download_handle = handle.clone();
     let chunk_store: FileChunks = Arc::new(Mutex::new(HashMap::new()));
 
+    let pair_handle = handle.clone();
+
     let upload_ws = warp::path("upload-ws")
         .and(warp::ws())
+        .and(with_ws_access(handle.clone(), "upload-ws"))
+        .and(with_chunk_format())
         .and(with_state(chunk_store.clone()))
         .and(with_handle(upload_handle))
-        .map(|ws: warp::ws::Ws, store, handle| {
-            ws.on_upgrade(move |socket| handle_upload_ws(socket, store, handle))
-        });
+        .map(
+            |ws: warp::ws::Ws,
+             access: Result<PairedClient, String>,
+             format: Result<ChunkFormat, String>,
+             store,
+             handle| {
+                match access.and_then(|client| format.map(|format| (client, format))) {
+                    Ok((client, format)) => ws
+                        .on_upgrade(move |socket| {
+                            handle_upload_ws(socket, store, handle, client, format)
+                        })
+                        .into_response(),
+                    Err(reason) => ws
+                        .on_upgrade(move |socket| refuse_ws(socket, reason))
+                        .into_response(),
+                }
+            },
+        );
 
     let download_ws = warp::path("download-ws")
         .and(warp::ws())
+        .and(with_ws_access(handle.clone(), "download-ws"))
         .and(with_handle(download_handle))
-        .map(|ws: warp::ws::Ws, handle| {
-            ws.on_upgrade(move |socket| handle_download_ws(socket, handle))
+        .map(|ws: warp::ws::Ws, access, handle| match access {
+            Ok(client) => ws
+                .on_upgrade(move |socket| handle_download_ws(socket, handle, client))
+                .into_response(),
+            Err(reason) => ws
+                .on_upgrade(move |socket| refuse_ws(socket, reason))
+                .into_response(),
         });
 
     let root_ws = warp::path::end()
         .and(warp::ws())
-        .map(|ws: warp::ws::Ws| ws.on_upgrade(move |socket| handle_root_ws(socket)));
+        .and(with_ws_access(handle.clone(), "ws"))
+        .and(with_handle(handle.clone()))
+        .map(|ws: warp::ws::Ws, access, handle| match access {
+            Ok(client) => ws
+                .on_upgrade(move |socket| handle_root_ws(socket, handle, client))
+                .into_response(),
+            Err(reason) => ws
+                .on_upgrade(move |socket| refuse_ws(socket, reason))
+                .into_response(),
+        });
+
+    let pair = warp::path("pair")
+        .and(with_allowed_origin(handle.clone(), "pair"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::content_length_limit(4 * 1024))
+        .and(warp::body::json())
+        .and(warp::addr::remote())
+        .and(with_handle(pair_handle))
+        .map(
+            |request: PairRequest, remote: Option<SocketAddr>, handle: AppHandle| {
+                match pairing::pair(&handle, remote.map(|remote| remote.ip()), request) {
+                    Ok(response) => {
+                        let _ = handle.emit("client-paired", response.client_id.clone());
+                        warp::reply::with_status(warp::reply::json(&response), StatusCode::OK)
+                    }
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&json!({ "error": e })),
+                        StatusCode::FORBIDDEN,
+                    ),
+                }
+            },
+        );
 
-    let routes = upload_ws
+    let api = upload_ws
         .or(download_ws)
         .or(root_ws)
-        .or(get_anttp_port())
-        .or(get_dweb_port());
+        .or(pair)
+        .or(with_allowed_origin(handle.clone(), "getAntTPPort").and(get_anttp_port()))
+        .or(with_allowed_origin(handle.clone(), "getDWebPort").and(get_dweb_port()))
+        .or(with_allowed_origin(handle.clone(), "protocol/schema").and(get_protocol_schema()))
+        .or(rest_api::routes(handle.clone()));
+
+    let routes = with_allowed_remote()
+        .and(api.clone())
+        .recover(handle_rejection);
+
+    let addr = network::listen_addr(port);
 
-    let addr = ([127, 0, 0, 1], port);
+    let (_, server) = warp::serve(routes)
+        .bind_with_graceful_shutdown(addr, wait_for_shutdown(shutdown_rx.clone()));
 
-    let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(addr, async move {
-        while !*shutdown_rx.borrow() {
-            if shutdown_rx.changed().await.is_err() {
-                break;
+    #[cfg(unix)]
+    if network::get_settings().unix_socket {
+        match bind_unix_socket() {
+            Ok((listener, path)) => {
+                log_info!(LogTarget::Websocket, "Listening on {}", path.display());
+
+                let incoming = futures::stream::unfold(listener, |listener| async move {
+                    let stream = listener.accept().await.map(|(stream, _)| stream);
+                    Some((stream, listener))
+                });
+                // access is limited by the socket's file mode, not by address
+                let unix_server = warp::serve(api.recover(handle_rejection))
+                    .serve_incoming_with_graceful_shutdown(
+                        incoming,
+                        wait_for_shutdown(shutdown_rx),
+                    );
+
+                futures::join!(server, unix_server);
+                let _ = std::fs::remove_file(path);
+                return;
             }
+            Err(e) => log_error!(LogTarget::Websocket, "No unix socket: {}", e),
         }
-    });
+    }
 
     server.await;
 }
 
+async fn wait_for_shutdown(mut shutdown_rx: watch::Receiver<bool>) {
+    while !*shutdown_rx.borrow() {
+        if shutdown_rx.changed().await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Bind the unix socket readable and writable by the current user only.
+#[cfg(unix)]
+fn bind_unix_socket() -> Result<(tokio::net::UnixListener, std::path::PathBuf), String> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    let path = network::unix_socket_path()?;
+
+    // a socket left behind by a crash blocks the bind, anything else is not ours to remove
+    if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{} exists and is not a socket", path.display()));
+        }
+        std::fs::remove_file(&path)
+            .map_err(|e| format!("Failed to remove stale {}: {}", path.display(), e))?;
+    }
+
+    let listener = tokio::net::UnixListener::bind(&path)
+        .map_err(|e| format!("Failed to bind {}: {}", path.display(), e))?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("Failed to restrict {}: {}", path.display(), e))?;
+
+    Ok((listener, path))
+}
+
 fn get_anttp_port() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path("getAntTPPort").map(|| {
         let port: u16 = *ANTTP_PORT.lock().unwrap();
@@ -115,7 +481,172 @@ fn get_dweb_port() -> impl Filter<Extract = impl warp::Reply, Error = warp::Reje
     })
 }
 
-fn with_handle(
+/// JSON Schemas of the websocket messages, readable without pairing.
+fn get_protocol_schema() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+{
+    warp::path!("protocol" / "schema")
+        .and(warp::get())
+        .map(|| warp::reply::json(&protocol::schema()))
+}
+
+#[derive(Debug)]
+struct Forbidden(String);
+
+impl warp::reject::Reject for Forbidden {}
+
+#[derive(Debug)]
+struct Unauthorized(String);
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// Refuse web pages that aren't on the origin allowlist.
+fn with_allowed_origin(
+    handle: AppHandle,
+    route: &'static str,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("origin")
+        .and_then(move |origin: Option<String>| {
+            let handle = handle.clone();
+            async move {
+                if origins::is_allowed(origin.as_deref()) {
+                    return Ok(());
+                }
+
+                let origin = origin.unwrap_or_default();
+                origins::report_rejection(&handle, &origin, route);
+                Err(warp::reject::custom(Forbidden(format!(
+                    "Origin {} is not allowed to use this SafeBox client",
+                    origin
+                ))))
+            }
+        })
+        .untuple_one()
+}
+
+/// The token handed out at pairing, either as `?token=` since browsers can't
+/// set headers on a websocket, or as a bearer token.
+fn with_token() -> impl Filter<Extract = (Option<String>,), Error = warp::Rejection> + Clone {
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .and(warp::header::optional::<String>("authorization"))
+        .map(|query: String, authorization: Option<String>| {
+            authorization
+                .as_deref()
+                .and_then(|header| header.strip_prefix("Bearer "))
+                .map(str::to_string)
+                .or_else(|| {
+                    serde_urlencoded::from_str::<HashMap<String, String>>(&query)
+                        .ok()?
+                        .remove("token")
+                })
+        })
+}
+
+/// Loopback always gets in, anyone else only when on the network allowlist.
+fn with_allowed_remote() -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::addr::remote()
+        .and_then(|remote: Option<SocketAddr>| async move {
+            if network::is_allowed(remote) {
+                return Ok(());
+            }
+
+            let remote = remote.map_or("unknown address".to_string(), |r| r.ip().to_string());
+            log_warn!(LogTarget::Websocket, "Refused connection from {}", remote);
+            Err(warp::reject::custom(Forbidden(format!(
+                "{} is not allowed to use this SafeBox client",
+                remote
+            ))))
+        })
+        .untuple_one()
+}
+
+/// The upload chunk format asked for with `?format=json|binary`.
+fn with_chunk_format(
+) -> impl Filter<Extract = (Result<ChunkFormat, String>,), Error = warp::Rejection> + Clone {
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .map(|query: String| {
+            let format = serde_urlencoded::from_str::<HashMap<String, String>>(&query)
+                .ok()
+                .and_then(|mut params| params.remove("format"));
+            ChunkFormat::parse(format.as_deref())
+        })
+}
+
+/// Origin and pairing checks for a websocket upgrade. Browsers hide the body
+/// of a failed handshake, so a refusal is upgraded anyway and closed with the
+/// reason.
+fn with_ws_access(
+    handle: AppHandle,
+    route: &'static str,
+) -> impl Filter<Extract = (Result<PairedClient, String>,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("origin")
+        .and(with_token())
+        .map(move |origin: Option<String>, token: Option<String>| {
+            if !origins::is_allowed(origin.as_deref()) {
+                let origin = origin.unwrap_or_default();
+                origins::report_rejection(&handle, &origin, route);
+                return Err(format!("Origin {} is not allowed", origin));
+            }
+
+            token
+                .as_deref()
+                .and_then(pairing::authenticate)
+                .ok_or_else(|| "Missing or revoked token, pair with SafeBox first".to_string())
+        })
+}
+
+/// Origin and pairing checks for plain HTTP routes.
+pub(crate) fn with_client(
+    handle: AppHandle,
+    route: &'static str,
+) -> impl Filter<Extract = (PairedClient,), Error = warp::Rejection> + Clone {
+    with_allowed_origin(handle, route)
+        .and(with_token())
+        .and_then(|token: Option<String>| async move {
+            token
+                .as_deref()
+                .and_then(pairing::authenticate)
+                .ok_or_else(|| {
+                    warp::reject::custom(Unauthorized(
+                        "Missing or revoked token, pair with SafeBox first".into(),
+                    ))
+                })
+        })
+}
+
+async fn refuse_ws(ws: WebSocket, mut reason: String) {
+    let (mut tx, _rx) = ws.split();
+
+    // close reasons are limited to 123 bytes
+    while reason.len() > 123 {
+        reason.pop();
+    }
+    let _ = tx.send(Message::close_with(POLICY_VIOLATION, reason)).await;
+}
+
+async fn handle_rejection(rejection: warp::Rejection) -> Result<impl warp::Reply, Infallible> {
+    let (status, message) = if let Some(Forbidden(reason)) = rejection.find() {
+        (StatusCode::FORBIDDEN, reason.clone())
+    } else if let Some(Unauthorized(reason)) = rejection.find() {
+        (StatusCode::UNAUTHORIZED, reason.clone())
+    } else if rejection.find::<warp::reject::PayloadTooLarge>().is_some() {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "Payload too large".to_string(),
+        )
+    } else if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, "Not found".to_string())
+    } else {
+        (StatusCode::BAD_REQUEST, format!("{:?}", rejection))
+    };
+
+    Ok(warp::reply::with_status(message, status))
+}
+
+pub(crate) fn with_handle(
     handle: AppHandle,
 ) -> impl Filter<Extract = (AppHandle,), Error = Infallible> + Clone {
     warp::any().map(move || handle.clone())
@@ -127,196 +658,579 @@ fn with_state(
     warp::any().map(move || store.clone())
 }
 
-type FileChunks = Arc<Mutex<HashMap<String, Vec<Option<Vec<u8>>>>>>;
+/// Chunks of an upload that hasn't been completed yet.
+struct PendingUpload {
+    client_id: String,
+    /// The upload socket it was last sent on.
+    connection: u64,
+    file: Option<FileInfo>,
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+    bytes: u64,
+    last_chunk: Instant,
+}
 
-async fn handle_upload_ws(ws: WebSocket, store: FileChunks, handle: AppHandle) {
-    let (mut tx, mut rx) = ws.split();
+type FileChunks = Arc<Mutex<HashMap<String, PendingUpload>>>;
 
-    println!("[WS] Upload WebSocket connection established");
-
-    while let Some(Ok(msg)) = rx.next().await {
-        if msg.is_text() {
-            match serde_json::from_str::<Chunk>(msg.to_str().unwrap()) {
-                Ok(chunk) => {
-                    let decoded_data = match decode(&chunk.data) {
-                        Ok(d) => d,
-                        Err(e) => {
-                            eprintln!("Base64 decode failed: {}", e);
-                            let error_msg = json!({
-                                "action": "uploadError",
-                                "upload_id": chunk.metadata.upload_id,
-                                "error": format!("Base64 decode failed: {}", e),
-                            });
-                            let _ = tx.send(Message::text(error_msg.to_string())).await;
-                            continue;
-                        }
-                    };
+async fn handle_upload_ws(
+    ws: WebSocket,
+    store: FileChunks,
+    handle: AppHandle,
+    client: PairedClient,
+    format: ChunkFormat,
+) {
+    let (mut tx, mut rx) = ws.split();
 
-                    const MAX_CHUNKS: usize = 100_000;
-                    let key = chunk.metadata.upload_id.clone();
-                    let total_chunks = chunk.metadata.total_chunks;
-
-                    if total_chunks == 0 || total_chunks > MAX_CHUNKS {
-                        eprintln!("Invalid total_chunks: {}", total_chunks);
-                        let error_msg = json!({
-                            "action": "uploadError",
-                            "upload_id": chunk.metadata.upload_id,
-                            "error": format!("Invalid total_chunks: {}", total_chunks),
-                        });
-                        let _ = tx.send(Message::text(error_msg.to_string())).await;
-                        continue;
-                    }
+    let Some(mut revocation) = pairing::revocation(&client.id) else {
+        close_revoked(&mut tx, &client).await;
+        return;
+    };
+    let Some((session, first)) =
+        handshake(&mut tx, &mut rx, &handle, &client, &mut revocation, format).await
+    else {
+        return;
+    };
+    let format = session.chunk_format;
+    let connection = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
 
-                    if chunk.metadata.chunk_index >= total_chunks {
-                        eprintln!(
-                            "Invalid chunk_index {} for total_chunks {}",
-                            chunk.metadata.chunk_index, total_chunks
-                        );
-                        let error_msg = json!({
-                            "action": "uploadError",
-                            "upload_id": chunk.metadata.upload_id,
-                            "error": format!(
-                                "Invalid chunk_index {} for total_chunks {}",
-                                chunk.metadata.chunk_index, total_chunks
-                            ),
-                        });
-                        let _ = tx.send(Message::text(error_msg.to_string())).await;
-                        continue;
-                    }
+    log_info!(
+        LogTarget::Websocket,
+        "Upload WebSocket connection established for {} ({} chunks, protocol {})",
+        client.name,
+        format.name(),
+        session.protocol_version
+    );
 
-                    let mut store_guard = store.lock().await;
-                    let entry = store_guard.entry(key.clone()).or_insert_with(|| {
-                        let mut vec = Vec::new();
-                        if vec.try_reserve_exact(total_chunks).is_err() {
-                            eprintln!("Memory allocation for chunk vector failed");
-                            return Vec::new();
-                        }
-                        vec.resize_with(total_chunks, || None);
-                        vec
-                    });
-
-                    entry[chunk.metadata.chunk_index] = Some(decoded_data);
-
-                    let ack = json!({
-                        "type": "chunk_received",
-                        "upload_id": chunk.metadata.upload_id,
-                        "chunk_index": chunk.metadata.chunk_index
-                    });
-
-                    if let Err(e) = tx.send(Message::text(ack.to_string())).await {
-                        eprintln!("Failed to send chunk ack: {}", e);
-                    }
+    let mut pending = first;
+    loop {
+        let msg = match pending.take() {
+            Some(msg) => msg,
+            None => tokio::select! {
+                msg = rx.next() => match msg {
+                    Some(Ok(msg)) => msg,
+                    _ => break,
+                },
+                _ = revoked(&mut revocation) => {
+                    close_revoked(&mut tx, &client).await;
+                    break;
+                }
+            },
+        };
 
-                    if entry.iter().all(|c| c.is_some()) {
-                        let mut full_data = Vec::new();
-                        for part in entry.iter().flatten() {
-                            full_data.extend_from_slice(part);
-                        }
-
-                        let output_path = data_dir()
-                            .expect("Cannot find data dir")
-                            .join("safebox")
-                            .join(&chunk.metadata.filename);
-
-                        tokio::fs::create_dir_all(output_path.parent().unwrap())
-                            .await
-                            .unwrap();
-
-                        let mut file = File::create(&output_path).await.unwrap();
-                        file.write_all(&full_data).await.unwrap();
-
-                        let file_data = tokio::fs::read(&output_path).await.unwrap();
-
-                        let payload = UploadFilePayload {
-                            name: chunk.metadata.filename.clone(),
-                            mime_type: chunk.metadata.mime_type.clone(),
-                            data: file_data,
-                        };
-
-                        let response = match do_upload(payload, &handle).await {
-                            Ok(xorname) => {
-                                handle
-                                    .emit(
-                                        "upload-file",
-                                        UploadFileEvent {
-                                            name: chunk.metadata.filename.clone(),
-                                            mime_type: chunk.metadata.mime_type.clone(),
-                                            xorname: Some(xorname.clone()),
-                                            success: true,
-                                            error: None,
-                                        },
-                                    )
-                                    .unwrap();
-
-                                json!({
-                                    "type": "upload_complete",
-                                    "upload_id": chunk.metadata.upload_id,
-                                    "xorname": xorname
-                                })
-                            }
-                            Err(error) => {
-                                handle
-                                    .emit(
-                                        "upload-file",
-                                        UploadFileEvent {
-                                            name: chunk.metadata.filename.clone(),
-                                            mime_type: chunk.metadata.mime_type.clone(),
-                                            xorname: None,
-                                            success: false,
-                                            error: Some(UploadError {
-                                                title: "Upload Failed".into(),
-                                                description: format!("{:?}", error),
-                                            }),
-                                        },
-                                    )
-                                    .unwrap();
-
-                                json!({
-                                    "action": "uploadError",
-                                    "upload_id": chunk.metadata.upload_id,
-                                    "error": format!("{:?}", error)
-                                })
-                            }
-                        };
-
-                        if let Err(e) = tx.send(Message::text(response.to_string())).await {
-                            eprintln!("Failed to send response to extension: {}", e);
-                        }
-
-                        store_guard.remove(&key);
-                    }
+        let chunk = if msg.is_binary() && format == ChunkFormat::Binary {
+            upload_frames::decode(msg.into_bytes()).map_err(|e| (None, e))
+        } else if msg.is_text() && !session.is_legacy() {
+            match serde_json::from_str::<ClientMessage>(msg.to_str().unwrap()) {
+                Ok(ClientMessage::Chunk(chunk)) if format == ChunkFormat::Json => {
+                    decode_json_chunk(chunk.metadata, &chunk.data)
+                }
+                Ok(ClientMessage::Chunk(_)) => Err((
+                    None,
+                    format!("This connection expects {} chunks", format.name()),
+                )),
+                Ok(ClientMessage::Resume(request)) => {
+                    let state = resume_state(&store, &client, request.upload_id).await;
+                    let _ = tx.send(reply(&session, &state)).await;
+                    continue;
+                }
+                Ok(other) => {
+                    let error = ServerMessage::Error {
+                        code: ErrorCode::UnexpectedMessage,
+                        error: format!("{} is not expected on the upload socket", other.kind()),
+                    };
+                    let _ = tx.send(reply(&session, &error)).await;
+                    continue;
                 }
                 Err(e) => {
-                    eprintln!("Failed to deserialize chunk JSON: {}", e);
+                    log_warn!(LogTarget::Websocket, "Invalid message: {}", e);
+                    let error = ServerMessage::Error {
+                        code: ErrorCode::InvalidMessage,
+                        error: e.to_string(),
+                    };
+                    let _ = tx.send(reply(&session, &error)).await;
+                    continue;
                 }
             }
+        } else if msg.is_text() && format == ChunkFormat::Json {
+            serde_json::from_str::<LegacyChunk>(msg.to_str().unwrap())
+                .map_err(|e| (None, format!("Failed to deserialize chunk JSON: {}", e)))
+                .and_then(|chunk| decode_json_chunk(chunk.metadata, &chunk.data))
+        } else if msg.is_text() || msg.is_binary() {
+            Err((
+                None,
+                format!("This connection expects {} chunks", format.name()),
+            ))
+        } else {
+            // ping, pong and close are handled by warp
+            continue;
+        };
+
+        match chunk {
+            Ok(chunk) => {
+                receive_chunk(
+                    &mut tx, &store, &handle, &session, &client, connection, chunk,
+                )
+                .await
+            }
+            Err((upload_id, e)) => {
+                log_error!(LogTarget::Websocket, "Rejected chunk: {}", e);
+                let _ = tx
+                    .send(upload_error(&session, upload_id.as_deref(), &e))
+                    .await;
+            }
         }
     }
 }
 
-async fn handle_download_ws(ws: WebSocket, handle: AppHandle) {
+/// Which chunks of an upload are already stored, so a client that lost its
+/// connection only sends the rest.
+async fn resume_state(
+    store: &FileChunks,
+    client: &PairedClient,
+    upload_id: String,
+) -> ServerMessage {
+    let store = store.lock().await;
+    let upload = store
+        .get(&upload_id)
+        .filter(|upload| upload.client_id == client.id);
+    ServerMessage::ResumeState {
+        total_chunks: upload.map(|upload| upload.chunks.len()),
+        received: upload
+            .map(|upload| {
+                upload
+                    .chunks
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, chunk)| chunk.is_some())
+                    .map(|(index, _)| index)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        upload_id,
+    }
+}
+
+/// A JSON chunk, whose bytes are base64 encoded.
+fn decode_json_chunk(
+    metadata: ChunkMetadata,
+    data: &str,
+) -> Result<ReceivedChunk, (Option<String>, String)> {
+    let data = decode(data).map_err(|e| {
+        (
+            Some(metadata.upload_id.clone()),
+            format!("Base64 decode failed: {}", e),
+        )
+    })?;
+
+    Ok(ReceivedChunk {
+        upload_id: metadata.upload_id,
+        chunk_index: metadata.chunk_index,
+        total_chunks: metadata.total_chunks,
+        file: Some(FileInfo {
+            filename: metadata.filename,
+            mime_type: metadata.mime_type,
+        }),
+        data,
+    })
+}
+
+/// Tell the client and the UI which limit was hit.
+async fn refuse_over_quota(
+    tx: &mut SplitSink<WebSocket, Message>,
+    handle: &AppHandle,
+    session: &Session,
+    client: &PairedClient,
+    upload_id: &str,
+    exceeded: QuotaExceeded,
+) {
+    quotas::report(handle, client, &exceeded);
+    let error = ServerMessage::QuotaExceeded {
+        upload_id: Some(upload_id.to_string()),
+        limit: exceeded.limit,
+        allowed: exceeded.allowed,
+        error: exceeded.error,
+    };
+    let _ = tx.send(reply(session, &error)).await;
+}
+
+fn upload_error(session: &Session, upload_id: Option<&str>, error: &str) -> Message {
+    let error = ServerMessage::UploadError {
+        upload_id: upload_id.map(str::to_string),
+        error: error.to_string(),
+    };
+    reply(session, &error)
+}
+
+/// Store a chunk, and upload the file once all of its chunks are in.
+async fn receive_chunk(
+    tx: &mut SplitSink<WebSocket, Message>,
+    store: &FileChunks,
+    handle: &AppHandle,
+    session: &Session,
+    client: &PairedClient,
+    connection: u64,
+    chunk: ReceivedChunk,
+) {
+    const MAX_CHUNKS: usize = 100_000;
+    let key = chunk.upload_id.clone();
+    let total_chunks = chunk.total_chunks;
+
+    if total_chunks == 0 || total_chunks > MAX_CHUNKS {
+        log_error!(
+            LogTarget::Websocket,
+            "Invalid total_chunks: {}",
+            total_chunks
+        );
+        let e = format!("Invalid total_chunks: {}", total_chunks);
+        let _ = tx.send(upload_error(session, Some(key.as_str()), &e)).await;
+        return;
+    }
+
+    if chunk.chunk_index >= total_chunks {
+        log_error!(
+            LogTarget::Websocket,
+            "Invalid chunk_index {} for total_chunks {}",
+            chunk.chunk_index,
+            total_chunks
+        );
+        let e = format!(
+            "Invalid chunk_index {} for total_chunks {}",
+            chunk.chunk_index, total_chunks
+        );
+        let _ = tx.send(upload_error(session, Some(key.as_str()), &e)).await;
+        return;
+    }
+
+    let chunk_bytes = chunk.data.len() as u64;
+    let mut store_guard = store.lock().await;
+
+    // unfinished uploads nobody came back for would hold their memory forever
+    let now = Instant::now();
+    store_guard.retain(|upload_id, upload| {
+        let alive = now.duration_since(upload.last_chunk) < PENDING_UPLOAD_TTL;
+        if !alive {
+            uploads::fail(
+                upload_id,
+                &upload.client_id,
+                "Abandoned before all chunks arrived",
+            );
+        }
+        alive
+    });
+
+    // every check runs before anything is stored or counted, a refused
+    // upload leaves no entry and uses up no hourly slot
+    let existing = store_guard.get(&key);
+    if existing.is_some_and(|upload| upload.client_id != client.id) {
+        drop(store_guard);
+        let e = format!("Upload {} belongs to another client", key);
+        let _ = tx.send(upload_error(session, Some(key.as_str()), &e)).await;
+        return;
+    }
+    if let Some(upload) = existing.filter(|upload| upload.chunks.len() != total_chunks) {
+        let e = format!(
+            "total_chunks changed from {} to {}",
+            upload.chunks.len(),
+            total_chunks
+        );
+        drop(store_guard);
+        let _ = tx.send(upload_error(session, Some(key.as_str()), &e)).await;
+        return;
+    }
+
+    // a resent chunk replaces the earlier copy
+    let usage = ChunkUsage {
+        upload_bytes: existing.map(|upload| upload.bytes),
+        replaced: existing
+            .and_then(|upload| upload.chunks[chunk.chunk_index].as_ref())
+            .map_or(0, |data| data.len() as u64),
+        chunk_bytes,
+        in_flight: store_guard
+            .values()
+            .filter(|upload| upload.client_id == client.id)
+            .map(|upload| upload.bytes)
+            .sum(),
+        running: store_guard
+            .values()
+            .filter(|upload| upload.connection == connection)
+            .count(),
+    };
+    let is_new = existing.is_none();
+
+    // before the quotas, a refusal here shouldn't use up an hourly slot
+    let mut chunks = Vec::new();
+    if is_new && chunks.try_reserve_exact(total_chunks).is_err() {
+        drop(store_guard);
+        log_error!(
+            LogTarget::Websocket,
+            "Memory allocation for chunk vector failed"
+        );
+        let _ = tx
+            .send(upload_error(
+                session,
+                Some(key.as_str()),
+                "Upload too large to buffer",
+            ))
+            .await;
+        return;
+    }
+
+    let upload_bytes = match quotas::admit_chunk(&client.id, &usage) {
+        Ok(upload_bytes) => upload_bytes,
+        Err(exceeded) => {
+            // too big to ever finish, free what it holds; otherwise the client
+            // may send the chunk again once other uploads are done
+            if exceeded.limit == QuotaLimit::UploadBytes && store_guard.remove(&key).is_some() {
+                uploads::fail(&key, &client.id, &exceeded.error);
+            }
+            drop(store_guard);
+            refuse_over_quota(tx, handle, session, client, &key, exceeded).await;
+            return;
+        }
+    };
+
+    if is_new {
+        chunks.resize_with(total_chunks, || None);
+        store_guard.insert(
+            key.clone(),
+            PendingUpload {
+                client_id: client.id.clone(),
+                connection,
+                file: None,
+                chunks,
+                received: 0,
+                bytes: 0,
+                last_chunk: now,
+            },
+        );
+    }
+    let entry = store_guard.get_mut(&key).unwrap();
+
+    if let Some(file) = &chunk.file {
+        if entry.file.is_none() {
+            uploads::begin(&key, &client.id, file, UploadState::Receiving);
+        }
+    }
+
+    entry.connection = connection;
+    entry.last_chunk = now;
+    entry.bytes = upload_bytes;
+    if let Some(file) = chunk.file {
+        entry.file = Some(file);
+    }
+    if entry.chunks[chunk.chunk_index]
+        .replace(chunk.data)
+        .is_none()
+    {
+        entry.received += 1;
+    }
+
+    let ack = ServerMessage::ChunkReceived {
+        upload_id: key.clone(),
+        chunk_index: chunk.chunk_index,
+    };
+
+    if let Err(e) = tx.send(reply(session, &ack)).await {
+        log_error!(LogTarget::Websocket, "Failed to send chunk ack: {}", e);
+    }
+
+    events::publish(PushEvent::UploadProgress(UploadProgress {
+        upload_id: key.clone(),
+        client_id: client.id.clone(),
+        received_chunks: entry.received,
+        total_chunks,
+    }));
+
+    if entry.received < total_chunks {
+        return;
+    }
+    let Some(upload) = store_guard.remove(&key) else {
+        return;
+    };
+    drop(store_guard);
+
+    // chunk 0 is required to carry it, and every chunk is in
+    let Some(file) = upload.file else {
+        let _ = tx
+            .send(upload_error(
+                session,
+                Some(key.as_str()),
+                "No file metadata received",
+            ))
+            .await;
+        return;
+    };
+
+    // the client's filename is only metadata, the data goes to a temp file
+    // that is deleted once stored
+    let temp_file = match write_temp_file(upload.chunks).await {
+        Ok(temp_file) => temp_file,
+        Err(e) => {
+            log_error!(LogTarget::Websocket, "{}", e);
+            uploads::fail(&key, &client.id, &e);
+            let _ = tx.send(upload_error(session, Some(key.as_str()), &e)).await;
+            return;
+        }
+    };
+
+    let stored = uploads::store(handle, &key, &client.id, &file, temp_file.path()).await;
+    drop(temp_file);
+    let response = match stored {
+        Ok(xorname) => {
+            let complete = ServerMessage::UploadComplete {
+                upload_id: key.clone(),
+                xorname,
+            };
+            reply(session, &complete)
+        }
+        Err(error) => upload_error(session, Some(key.as_str()), &error),
+    };
+
+    if let Err(e) = tx.send(response).await {
+        log_error!(
+            LogTarget::Websocket,
+            "Failed to send response to extension: {}",
+            e
+        );
+    }
+}
+
+async fn write_temp_file(chunks: Vec<Option<Vec<u8>>>) -> Result<NamedTempFile, String> {
+    let temp_file =
+        NamedTempFile::new().map_err(|e| format!("Failed to create temp file: {}", e))?;
+    let mut output = File::create(temp_file.path())
+        .await
+        .map_err(|e| format!("Failed to open temp file: {}", e))?;
+    for part in chunks.into_iter().flatten() {
+        output
+            .write_all(&part)
+            .await
+            .map_err(|e| format!("Failed to write temp file: {}", e))?;
+    }
+    output
+        .flush()
+        .await
+        .map_err(|e| format!("Failed to write temp file: {}", e))?;
+    Ok(temp_file)
+}
+
+async fn handle_download_ws(ws: WebSocket, handle: AppHandle, client: PairedClient) {
     let (mut tx, mut rx) = ws.split();
 
-    if let Some(Ok(msg)) = rx.next().await {
-        if msg.is_text() {
-            match serde_json::from_str::<DownloadRequest>(msg.to_str().unwrap()) {
-                Ok(req) if req.action == "download" => {
-                    handle
-                        .emit(
-                            "show-toast",
-                            ToastEvent {
-                                title: "Success".into(),
-                                description: "Download request sent to client".into(),
-                            },
-                        )
-                        .unwrap();
-                    // Implement actual download logic as needed
-                }
-                _ => {
-                    let error_response = json!({ "error": "Invalid request format or xorname" });
-                    let _ = tx.send(Message::text(error_response.to_string())).await;
-                }
+    log_info!(
+        LogTarget::Websocket,
+        "Download WebSocket connection established for {}",
+        client.name
+    );
+
+    let Some(mut revocation) = pairing::revocation(&client.id) else {
+        close_revoked(&mut tx, &client).await;
+        return;
+    };
+    let Some((session, first)) = handshake(
+        &mut tx,
+        &mut rx,
+        &handle,
+        &client,
+        &mut revocation,
+        ChunkFormat::Json,
+    )
+    .await
+    else {
+        return;
+    };
+    let msg = match first {
+        Some(msg) => Some(msg),
+        None => tokio::select! {
+            msg = rx.next() => msg.and_then(Result::ok),
+            _ = revoked(&mut revocation) => {
+                close_revoked(&mut tx, &client).await;
+                return;
+            }
+        },
+    };
+
+    let Some(text) = msg.as_ref().and_then(|msg| msg.to_str().ok()) else {
+        return;
+    };
+
+    let request = if session.is_legacy() {
+        serde_json::from_str::<LegacyDownloadRequest>(text)
+            .ok()
+            .filter(|request| request.action == "download")
+            .map(|request| request.xorname)
+            .ok_or_else(|| "Invalid request format or xorname".to_string())
+    } else {
+        match serde_json::from_str::<ClientMessage>(text) {
+            Ok(ClientMessage::Download(request)) => Ok(request.xorname),
+            Ok(other) => Err(format!(
+                "{} is not expected on the download socket",
+                other.kind()
+            )),
+            Err(e) => Err(e.to_string()),
+        }
+    };
+
+    let xorname = match request {
+        Ok(xorname) => xorname,
+        Err(error) => {
+            let _ = tx
+                .send(reply(&session, &ServerMessage::DownloadError { error }))
+                .await;
+            return;
+        }
+    };
+
+    let result = tokio::select! {
+        result = download_to_downloads_dir(&xorname, &handle) => result,
+        _ = revoked(&mut revocation) => {
+            close_revoked(&mut tx, &client).await;
+            return;
+        }
+    };
+    let response = match result {
+        Ok(path) => {
+            log_info!(
+                LogTarget::Websocket,
+                "Downloaded {} for {} to {}",
+                xorname,
+                client.name,
+                path.display()
+            );
+            let _ = handle.emit(
+                "show-toast",
+                ToastEvent {
+                    title: "Download complete".into(),
+                    description: format!("Saved to {}", path.display()),
+                },
+            );
+            ServerMessage::DownloadComplete {
+                xorname,
+                path: path.to_string_lossy().into_owned(),
             }
         }
+        Err(error) => {
+            log_error!(
+                LogTarget::Websocket,
+                "Download of {} failed: {}",
+                xorname,
+                error
+            );
+            ServerMessage::DownloadError { error }
+        }
+    };
+    let _ = tx.send(reply(&session, &response)).await;
+}
+
+/// Fetch a file into the user's downloads folder, named after its xorname.
+async fn download_to_downloads_dir(xorname: &str, handle: &AppHandle) -> Result<PathBuf, String> {
+    // checked before it becomes part of a path
+    if !crate::is_xorname(xorname) {
+        return Err(format!("'{}' is not an xorname", xorname));
     }
+    let dest = dirs::download_dir()
+        .ok_or("Could not find the downloads directory")?
+        .join(xorname);
+    crate::download_path(xorname, &dest, handle).await?;
+    Ok(dest)
 }
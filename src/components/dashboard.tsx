@@ -2,11 +2,33 @@ import { useEffect, useState } from "react";
 import { toast } from "react-toastify";
 import { listen } from "@tauri-apps/api/event";
 import { invoke } from "@tauri-apps/api/core";
+import { ask } from "@tauri-apps/plugin-dialog";
 import { download } from "@/backend/logic";
 import { UploadPayload } from "@/types/upload-file-event";
-import { ToastPayload } from "@/types/payloads";
+import {
+    IntegrityReport,
+    OriginRejectedPayload,
+    PairedClient,
+    PairingCode,
+    PortDiagnosis,
+    PortOwner,
+    PortReassignment,
+    QuotaExceededPayload,
+    QuotaLimit,
+    ServiceStatePayload,
+    ServiceStatus,
+    SidecarRecord,
+    ToastPayload,
+} from "@/types/payloads";
 import { Button } from "./ui/button";
 
+const QUOTA_LIMIT_NAMES: Record<QuotaLimit, string> = {
+    upload_bytes: "upload size",
+    concurrent_uploads: "concurrent uploads",
+    in_flight_bytes: "pending data",
+    uploads_per_hour: "hourly uploads",
+};
+
 export default function Dashboard() {
     const [isClientRunning, setIsClientRunning] = useState(false);
     const [antPort, setAntPort] = useState<number>(8081);
@@ -14,11 +36,24 @@ export default function Dashboard() {
     const [dwebPort, setDwebPort] = useState<number>(5537);
     const [websocketPort, setWebsocketPort] = useState<number>(8084);
     const [portToKill, setPortToKill] = useState<number>(0);
+    const [portDiagnoses, setPortDiagnoses] = useState<PortDiagnosis[]>([]);
+    const [pairingCode, setPairingCode] = useState<PairingCode | null>(null);
+    const [pairedClients, setPairedClients] = useState<PairedClient[]>([]);
+    const [orphans, setOrphans] = useState<SidecarRecord[]>([]);
+    const [integrityFailures, setIntegrityFailures] = useState<
+        IntegrityReport[]
+    >([]);
 
     const checkServerRunning = async () => {
         try {
-            const running = await invoke<boolean>("is_server_running");
-            setIsClientRunning(running);
+            const statuses = await invoke<ServiceStatus[]>(
+                "get_service_status"
+            );
+            setIsClientRunning(
+                statuses.some(
+                    (s) => s.state === "Starting" || s.state === "Ready"
+                )
+            );
         } catch {
             setIsClientRunning(false);
         }
@@ -49,9 +84,55 @@ export default function Dashboard() {
         }
     };
 
+    const refreshOrphans = async () => {
+        try {
+            setOrphans(
+                await invoke<SidecarRecord[]>("list_orphaned_sidecars")
+            );
+        } catch {
+            setOrphans([]);
+        }
+    };
+
+    const verifySidecars = async () => {
+        try {
+            const reports = await invoke<IntegrityReport[]>("verify_sidecars");
+            setIntegrityFailures(reports.filter((r) => !r.ok));
+        } catch (e) {
+            toast.error("Failed to verify sidecars: " + e);
+        }
+    };
+
+    const resolveOrphan = async (
+        orphan: SidecarRecord,
+        action: "adopt" | "terminate"
+    ) => {
+        try {
+            if (action === "adopt") {
+                await invoke("adopt_orphaned_sidecar", {
+                    service: orphan.service,
+                });
+                toast.success(`${orphan.service} adopted`);
+            } else {
+                await invoke("terminate_orphaned_sidecar", {
+                    service: orphan.service,
+                });
+                toast.info(`${orphan.service} terminated`);
+            }
+        } catch (e: any) {
+            toast.error(`Failed to ${action} ${orphan.service}: ${e}`);
+        }
+        await refreshOrphans();
+        await refreshPorts();
+        await checkServerRunning();
+    };
+
     useEffect(() => {
         checkServerRunning();
         refreshPorts();
+        refreshOrphans();
+        refreshPairedClients();
+        verifySidecars();
 
         const unlistenDownload = listen<string>(
             "download-file",
@@ -98,10 +179,81 @@ export default function Dashboard() {
             toast(title + ": " + description);
         });
 
+        const unlistenServiceState = listen<ServiceStatePayload>(
+            "service-state",
+            (event) => {
+                const { service, state, message } = event.payload;
+                if (state === "Crashed") {
+                    toast.error(`${service}: ${message ?? "crashed"}`);
+                }
+                checkServerRunning();
+            }
+        );
+
+        const unlistenOrphans = listen<SidecarRecord[]>(
+            "orphaned-sidecars",
+            (event) => setOrphans(event.payload)
+        );
+
+        const unlistenIntegrity = listen<IntegrityReport>(
+            "sidecar-integrity",
+            (event) => {
+                const report = event.payload;
+                setIntegrityFailures((failures) => [
+                    ...failures.filter((f) => f.service !== report.service),
+                    report,
+                ]);
+            }
+        );
+
+        const unlistenPortReassigned = listen<PortReassignment>(
+            "port-reassigned",
+            (event) => {
+                const { port, requested, actual } = event.payload;
+                toast.info(
+                    `${port} port ${requested} was in use, using ${actual} instead`
+                );
+                refreshPorts();
+            }
+        );
+
+        const unlistenPaired = listen<string>("client-paired", () => {
+            setPairingCode(null);
+            toast.success("Extension paired");
+            refreshPairedClients();
+        });
+
+        const unlistenOriginRejected = listen<OriginRejectedPayload>(
+            "origin-rejected",
+            (event) => {
+                const { origin, route } = event.payload;
+                toast.warn(
+                    `Blocked ${origin || "a page"} from reaching SafeBox (${route})`
+                );
+            }
+        );
+
+        const unlistenQuotaExceeded = listen<QuotaExceededPayload>(
+            "quota-exceeded",
+            (event) => {
+                const { client_name, limit, error } = event.payload;
+                toast.warn(
+                    `${client_name} hit the ${QUOTA_LIMIT_NAMES[limit]} limit: ${error}`
+                );
+            }
+        );
+
         return () => {
             unlistenDownload.then((fn) => fn());
             unlistenUpload.then((fn) => fn());
             unlistenToast.then((fn) => fn());
+            unlistenServiceState.then((fn) => fn());
+            unlistenOrphans.then((fn) => fn());
+            unlistenIntegrity.then((fn) => fn());
+            unlistenPortReassigned.then((fn) => fn());
+            unlistenPaired.then((fn) => fn());
+            unlistenOriginRejected.then((fn) => fn());
+            unlistenQuotaExceeded.then((fn) => fn());
         };
     }, []);
 
@@ -135,7 +287,13 @@ export default function Dashboard() {
                     return;
                 }
 
-                await invoke("start_server");
+                try {
+                    await invoke("start_server");
+                } catch (e) {
+                    // show who holds the port instead of leaving the user at the dialog
+                    await diagnosePorts();
+                    throw e;
+                }
                 toast.success("Server started");
                 setIsClientRunning(true);
             } else {
@@ -231,6 +389,44 @@ export default function Dashboard() {
         }
     };
 
+    const refreshPairedClients = async () => {
+        try {
+            setPairedClients(
+                await invoke<PairedClient[]>("list_paired_clients")
+            );
+        } catch {
+            setPairedClients([]);
+        }
+    };
+
+    const startPairing = async () => {
+        try {
+            setPairingCode(await invoke<PairingCode>("start_pairing"));
+        } catch (e: any) {
+            toast.error("Failed to start pairing: " + e);
+        }
+    };
+
+    const revokeClient = async (client: PairedClient) => {
+        try {
+            await invoke("revoke_paired_client", { id: client.id });
+            toast.info(`${client.name} unpaired`);
+        } catch (e: any) {
+            toast.error(`Failed to unpair ${client.name}: ${e}`);
+        }
+        await refreshPairedClients();
+    };
+
+    const diagnosePorts = async () => {
+        try {
+            setPortDiagnoses(
+                await invoke<PortDiagnosis[]>("diagnose_ports")
+            );
+        } catch (e: any) {
+            toast.error("Failed to diagnose ports: " + e);
+        }
+    };
+
     const handleKillPort = async () => {
         if (portToKill < 1 || portToKill > 65535) {
             toast.error("Invalid port number to kill");
@@ -238,8 +434,39 @@ export default function Dashboard() {
         }
 
         try {
-            await invoke("kill_process_on_port", { port: portToKill });
-            toast.success(`Process on port ${portToKill} killed`);
+            const owners = await invoke<PortOwner[]>("find_port_owners", {
+                port: portToKill,
+            });
+            if (owners.length === 0) {
+                toast.info(`Nothing is listening on port ${portToKill}`);
+                return;
+            }
+
+            for (const owner of owners) {
+                const who = `${owner.name ?? "unknown process"} (pid ${
+                    owner.pid ?? "?"
+                }, user ${owner.user ?? owner.uid ?? "?"})`;
+                if (!owner.killable || owner.pid === undefined) {
+                    toast.error(
+                        `Port ${portToKill} is held by ${who}, which SafeBox may not stop`
+                    );
+                    continue;
+                }
+
+                const confirmed = await ask(
+                    `Stop ${who}${owner.exe ? `\n${owner.exe}` : ""}?`,
+                    { title: `Port ${portToKill} in use`, kind: "warning" }
+                );
+                if (!confirmed) {
+                    continue;
+                }
+
+                await invoke("kill_process_on_port", {
+                    port: portToKill,
+                    pid: owner.pid,
+                });
+                toast.success(`Stopped ${who}`);
+            }
         } catch (e: any) {
             toast("Failed to kill process" + ": " + e.toString());
         }
@@ -247,6 +474,34 @@ export default function Dashboard() {
 
     return (
         <div className="p-4 space-y-2">
+            {integrityFailures.map((failure) => (
+                <div
+                    key={failure.service}
+                    className="border border-red-500 text-red-500 rounded p-2"
+                >
+                    {`${failure.service} failed its integrity check and will not be started: ${failure.error ?? "checksum mismatch"}`}
+                </div>
+            ))}
+
+            {orphans.map((orphan) => (
+                <div
+                    key={orphan.service}
+                    className="flex flex-row gap-2 items-center border rounded p-2"
+                >
+                    <span>
+                        {`${orphan.service} from a previous session is still running (pid ${orphan.pid}, port ${orphan.port})`}
+                    </span>
+                    <Button onClick={() => resolveOrphan(orphan, "adopt")}>
+                        Adopt
+                    </Button>
+                    <Button
+                        onClick={() => resolveOrphan(orphan, "terminate")}
+                    >
+                        Terminate
+                    </Button>
+                </div>
+            ))}
+
             <div className="flex flex-row gap-2 items-center">
                 <Button onClick={toggleClient} className="cursor-pointer">
                     {isClientRunning ? "Stop Server" : "Start Server"}
@@ -361,7 +616,63 @@ export default function Dashboard() {
                 <Button onClick={handleKillPort}>
                     Kill Process using port
                 </Button>
+                <Button onClick={diagnosePorts}>Diagnose ports</Button>
             </div>
+
+            <div className="flex flex-col gap-2 p-4 border-t -mx-4">
+                <div className="flex flex-row gap-2 items-center">
+                    <Button onClick={startPairing}>Pair extension</Button>
+                    {pairingCode && (
+                        <span>
+                            {`Enter ${pairingCode.code} in the extension within ${Math.round(pairingCode.expires_in_secs / 60)} minutes`}
+                        </span>
+                    )}
+                </div>
+                {pairedClients.map((client) => (
+                    <div
+                        key={client.id}
+                        className="flex flex-row gap-2 items-center"
+                    >
+                        <span>
+                            {`${client.name}, paired ${new Date(client.paired_at_ms).toLocaleString()}${
+                                client.last_seen_ms
+                                    ? `, last seen ${new Date(client.last_seen_ms).toLocaleString()}`
+                                    : ""
+                            }`}
+                        </span>
+                        <Button onClick={() => revokeClient(client)}>
+                            Revoke
+                        </Button>
+                    </div>
+                ))}
+            </div>
+
+            {portDiagnoses.map((diagnosis) => (
+                <div
+                    key={diagnosis.port}
+                    className="flex flex-col border rounded p-2"
+                >
+                    <span>
+                        {`${diagnosis.port} port ${diagnosis.number}: ${
+                            !diagnosis.in_use
+                                ? "free"
+                                : diagnosis.ours
+                                  ? "in use by SafeBox"
+                                  : "in use"
+                        }`}
+                    </span>
+                    {diagnosis.owners.map((owner, i) => (
+                        <span key={i} className="text-sm">
+                            {`${owner.name ?? "unknown"} (pid ${owner.pid ?? "?"}, user ${owner.user ?? owner.uid ?? "?"})${owner.exe ? ` ${owner.exe}` : ""}`}
+                        </span>
+                    ))}
+                    {diagnosis.hint && (
+                        <span className="text-sm text-amber-600">
+                            {diagnosis.hint}
+                        </span>
+                    )}
+                </div>
+            ))}
         </div>
     );
 }
@@ -5,6 +5,7 @@ import { toast } from "react-toastify";
 import { useStorage } from "@/providers/storage-provider";
 import { useTranslation } from "react-i18next";
 import { invoke } from "@tauri-apps/api/core";
+import { ServiceStatus } from "@/types/payloads";
 
 export default function WalletSettings() {
     const { t } = useTranslation();
@@ -15,8 +16,14 @@ export default function WalletSettings() {
 
     const checkServerRunning = async () => {
         try {
-            const running = await invoke<boolean>("is_server_running");
-            setIsClientRunning(running);
+            const statuses = await invoke<ServiceStatus[]>(
+                "get_service_status"
+            );
+            setIsClientRunning(
+                statuses.some(
+                    (s) => s.state === "Starting" || s.state === "Ready"
+                )
+            );
         } catch {
             setIsClientRunning(false);
         }
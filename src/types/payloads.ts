@@ -2,3 +2,110 @@ export type ToastPayload = {
     title: string;
     description: string;
 };
+
+export type ServiceName = "ant" | "anttp" | "dweb";
+
+export type ServiceState = "Stopped" | "Starting" | "Ready" | "Crashed";
+
+export type ServiceStatePayload = {
+    service: ServiceName;
+    state: ServiceState;
+    exit_code?: number;
+    restart_count: number;
+    message?: string;
+};
+
+export type ServiceStatus = {
+    service: ServiceName;
+    state: ServiceState;
+    pid?: number;
+    port: number;
+    uptime_secs?: number;
+    restart_count: number;
+    last_exit_code?: number;
+};
+
+export type SidecarRecord = {
+    service: ServiceName;
+    pid: number;
+    port: number;
+    exe: string;
+    start_time?: number;
+};
+
+export type IntegrityReport = {
+    service: ServiceName;
+    path: string;
+    expected?: string;
+    actual?: string;
+    ok: boolean;
+    error?: string;
+};
+
+export type ManagedPort = ServiceName | "websocket";
+
+export type PortReassignment = {
+    port: ManagedPort;
+    requested: number;
+    actual: number;
+};
+
+export type PortOwner = {
+    port: number;
+    pid?: number;
+    name?: string;
+    exe?: string;
+    uid?: number;
+    user?: string;
+    killable: boolean;
+};
+
+export type PortDiagnosis = {
+    port: ManagedPort;
+    number: number;
+    in_use: boolean;
+    owners: PortOwner[];
+    ours: boolean;
+    stale_instance: boolean;
+    hint?: string;
+};
+
+export type PairingCode = {
+    code: string;
+    expires_in_secs: number;
+};
+
+export type PairedClient = {
+    id: string;
+    name: string;
+    paired_at_ms: number;
+    last_seen_ms?: number;
+};
+
+export type OriginRejectedPayload = {
+    origin: string;
+    route: string;
+    timestamp_ms: number;
+};
+
+export type QuotaLimit =
+    | "upload_bytes"
+    | "concurrent_uploads"
+    | "in_flight_bytes"
+    | "uploads_per_hour";
+
+export type UploadQuotas = {
+    max_upload_bytes: number;
+    max_concurrent_uploads: number;
+    max_in_flight_bytes: number;
+    max_uploads_per_hour: number;
+};
+
+export type QuotaExceededPayload = {
+    client_id: string;
+    client_name: string;
+    limit: QuotaLimit;
+    allowed: number;
+    error: string;
+    timestamp_ms: number;
+};